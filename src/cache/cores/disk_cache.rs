@@ -0,0 +1,95 @@
+use super::super::*;
+use crate::cache::disk::{self, DiskCacheLookup};
+use crate::CONFIG;
+use std::collections::HashSet;
+
+/// Locally stored file, `last_used` is the value of the usage counter
+struct DiskCacheEntry {
+    size: u64,
+    last_used: u64,
+}
+
+/// All the disk cache related directives are processed here
+pub async fn handler(mut rx: Receiver<DirectiveDiskCache>) {
+    let mut entries: HashMap<String, DiskCacheEntry> =
+        tokio::task::spawn_blocking(disk::load_entries)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, size)| (key, DiskCacheEntry { size, last_used: 0 }))
+            .collect();
+    let mut total_size: u64 = entries.values().map(|entry| entry.size).sum();
+    let mut filling = HashSet::<String>::new();
+    let mut usage_counter: u64 = 0;
+
+    while let Some(directive) = rx.recv().await {
+        match directive {
+            // Returns the cached file or allows a single caller to fill the cache
+            DirectiveDiskCache::Lookup {
+                key,
+                size,
+                callback,
+            } => {
+                if let Some(entry) = entries.get_mut(&key) {
+                    usage_counter += 1;
+                    entry.last_used = usage_counter;
+                    callback
+                        .send(DiskCacheLookup::Hit(disk::data_path(&key)))
+                        .ok();
+                } else if filling.contains(&key)
+                    || size > CONFIG.disk_cache_max_file_size
+                    || size > CONFIG.disk_cache_max_size
+                {
+                    callback.send(DiskCacheLookup::Miss).ok();
+                } else {
+                    filling.insert(key);
+                    callback.send(DiskCacheLookup::Fill).ok();
+                }
+            }
+
+            // Registers the downloaded file and evicts the least recently used ones
+            DirectiveDiskCache::Insert { key, size } => {
+                filling.remove(&key);
+                usage_counter += 1;
+                total_size += size;
+                if let Some(old) = entries.insert(
+                    key.clone(),
+                    DiskCacheEntry {
+                        size,
+                        last_used: usage_counter,
+                    },
+                ) {
+                    total_size -= old.size;
+                }
+
+                let mut evicted = Vec::new();
+                while total_size > CONFIG.disk_cache_max_size {
+                    let oldest = entries
+                        .iter()
+                        .filter(|(other, _)| **other != key)
+                        .min_by_key(|(_, entry)| entry.last_used)
+                        .map(|(other, _)| other.clone());
+
+                    match oldest.and_then(|oldest| entries.remove_entry(&oldest)) {
+                        Some((oldest, entry)) => {
+                            total_size -= entry.size;
+                            evicted.push(oldest);
+                        }
+                        None => break,
+                    }
+                }
+
+                if !evicted.is_empty() {
+                    debug!("Evicting {} files from the disk cache", evicted.len());
+                    tokio::task::spawn_blocking(move || {
+                        evicted.iter().for_each(|key| disk::remove_entry_file(key))
+                    });
+                }
+            }
+
+            DirectiveDiskCache::Abort { key } => {
+                filling.remove(&key);
+            }
+        }
+    }
+}
@@ -1,5 +1,11 @@
 use super::super::*;
+use crate::cache::disk;
+use crate::cache::ftp::{PrefetchReader, TransferSource};
+use crate::cache::listing::{ListingCache, ListingInvalidation};
+use crate::cache::worker::{self, Priority, SessionFile, SessionPool, SessionWorker};
+use crate::utils::throttle::{Direction, Limiter};
 use crate::utils::time;
+use crate::CONFIG;
 
 pub struct FtpSender<T> {
     inner: mpsc::Sender<DirectiveFTP>,
@@ -52,20 +58,27 @@ impl<T> FtpSender<T> {
 pub struct Resources {
     in_usage: bool,
     last_usage_timestamp: i64,
+
+    /// kept in memory only, used to open additional ssh sessions for the client
+    login_data: LoginData,
+
+    listings: ListingCache,
 }
 
-impl Default for Resources {
-    fn default() -> Self {
+impl Resources {
+    fn new(login_data: LoginData) -> Self {
         Self {
             in_usage: false,
             last_usage_timestamp: time::now(),
+            login_data,
+            listings: ListingCache::new(CONFIG.listing_cache_ttl_s),
         }
     }
 }
 
 /// All the ftp cache related directives are processed here
 pub async fn handler(mut rx: Receiver<DirectiveFTP>) {
-    let mut ftp_clients = HashMap::<FtpClientID, Arc<Sftp>>::new();
+    let mut ftp_clients = HashMap::<FtpClientID, SessionPool>::new();
     let mut ftp_resources = HashMap::<FtpClientID, Resources>::new();
 
     while let Some(directive) = rx.recv().await {
@@ -89,8 +102,8 @@ pub async fn handler(mut rx: Receiver<DirectiveFTP>) {
                     tokio::task::spawn_blocking(move || {
                         callback
                             .send(
-                                ftp::connect(login_data)
-                                    .map(|ftp| Cache::add_ftp_client_blocking(id, ftp)),
+                                ftp::connect(&login_data)
+                                    .map(|ftp| Cache::add_ftp_client_blocking(id, ftp, login_data)),
                             )
                             .ok();
                     });
@@ -99,9 +112,57 @@ pub async fn handler(mut rx: Receiver<DirectiveFTP>) {
 
             // Private directive
             // Adds the client to the map
-            DirectiveFTP::SFTPAddClient { id, stream } => {
-                ftp_clients.insert(id.clone(), Arc::new(stream));
-                ftp_resources.insert(id, Resources::default());
+            DirectiveFTP::SFTPAddClient {
+                id,
+                stream,
+                slot,
+                login_data,
+            } => {
+                let worker = SessionWorker::spawn(stream, slot, worker_name(&id));
+                ftp_clients.insert(id.clone(), SessionPool::new(worker));
+                ftp_resources.insert(id.clone(), Resources::new(login_data));
+                spawn(discard_leftovers(id));
+            }
+
+            DirectiveFTP::SFTPRequestSession { id } => {
+                if let (Some(pool), Some(resources)) =
+                    (ftp_clients.get(&id), ftp_resources.get(&id))
+                {
+                    if pool.begin_grow(true) {
+                        open_session(id, resources);
+                    }
+                }
+            }
+
+            // Private directive
+            // Adds the additional session to the pool of the client
+            DirectiveFTP::SFTPAddSession { id, connection } => {
+                let pool = if let Some(pool) = ftp_clients.get(&id) {
+                    pool
+                } else {
+                    // the client has been removed in the meantime, the connection is dropped
+                    continue;
+                };
+
+                match connection {
+                    Ok((stream, slot)) => {
+                        debug!("Opened additional ssh session for ({id})");
+                        pool.end_grow(Some(SessionWorker::spawn(stream, slot, worker_name(&id))));
+                    }
+                    Err(error) => {
+                        debug!("Cannot open additional ssh session for ({id}) because {error}");
+                        pool.end_grow(None);
+                    }
+                }
+            }
+
+            // Returns credentials of the client, used to open additional ssh sessions
+            DirectiveFTP::SFTPCredentials { id, callback } => {
+                let login_data = ftp_resources.get_mut(&id).map(|resources| {
+                    resources.last_usage_timestamp = time::now();
+                    resources.login_data.clone()
+                });
+                callback.send(login_data).ok();
             }
 
             // Check if an sftp client exists
@@ -115,195 +176,153 @@ pub async fn handler(mut rx: Receiver<DirectiveFTP>) {
                 callback,
                 ftp_directive,
             } => {
-                // get ftp client
-                let stream = if let Some(stream) = ftp_clients.get(&id) {
-                    stream.clone()
-                } else {
+                // check if the ftp client exists
+                if !ftp_clients.contains_key(&id) {
                     callback.send(false).ok();
                     continue;
-                };
+                }
 
                 // update usage timestamp for the client
-                if let Some(resources) = ftp_resources.get_mut(&id) {
+                let resources = if let Some(resources) = ftp_resources.get_mut(&id) {
                     resources.last_usage_timestamp = time::now();
                     callback.send(true).ok();
+                    resources
                 } else {
-                    ftp_clients.remove(&id);
+                    if let Some(pool) = ftp_clients.remove(&id) {
+                        pool.close();
+                    }
                     callback.send(false).ok();
                     continue;
-                }
-
-                // spawn task responsible for executing ftp command
-                tokio::task::spawn_blocking(move || {
-                    match ftp_directive {
-                        // List all entities in the dir
-                        DirectiveExecuteFTP::ReadDir { dir, callback } => {
-                            callback
-                                .send(stream.readdir(dir.as_ref()).map_err(|e| e.into()))
-                                .ok();
-                        }
-
-                        // Stream file content [deprecated]
-                        DirectiveExecuteFTP::ReadFile { file, callback } => {
-                            // try to open the file
-                            let mut file = match stream.open(file.as_ref()) {
-                                Ok(file) => file,
-                                Err(error) => {
-                                    callback.send(Err(error.into())).ok();
-                                    return;
-                                }
-                            };
-
-                            // create an mpsc channel for streaming file bytes and return the sender in the callback
-                            let (file_tx, mut file_rx) = mpsc::channel::<(
-                                usize,
-                                oneshot::Sender<anyhow::Result<Vec<u8>>>,
-                            )>(32);
-                            callback.send(Ok(file_tx)).ok();
-
-                            // stream bytes of the file
-                            while let Some((buffer_size, callback)) = file_rx.blocking_recv() {
-                                let mut buffer = vec![0u8; buffer_size];
-                                match file.read(&mut buffer) {
-                                    Ok(read_size) => {
-                                        buffer.drain(read_size..);
-                                        if callback.send(Ok(buffer)).is_err() {
-                                            return;
-                                        };
-
-                                        if read_size == 0 {
-                                            return;
-                                        }
-                                    }
-                                    Err(error) => {
-                                        callback.send(Err(error.into())).ok();
-                                        return;
-                                    }
-                                }
-                            }
+                };
 
-                            // cleanup
-                            file_rx.close();
-                            drop(file);
+                // recently read listings don't require the sftp round trip
+                let ftp_directive = match ftp_directive {
+                    DirectiveExecuteFTP::ReadDir {
+                        dir,
+                        refresh: false,
+                        callback,
+                    } => match resources.listings.get(&dir, time::now()) {
+                        Some(listing) => {
+                            callback.send(Ok(listing)).ok();
+                            continue;
                         }
-
-                        // Start or continue the file transfer
-                        DirectiveExecuteFTP::TransferFile {
-                            transfer_id,
-                            filename,
+                        None => DirectiveExecuteFTP::ReadDir {
+                            dir,
+                            refresh: false,
                             callback,
-                        } => {
-                            let transfer_info = if let Some(transfer_info) =
-                                Cache::get_transfer_info_blocking(transfer_id.clone())
-                            {
-                                transfer_info
-                            } else {
-                                if filename.is_none() {
-                                    callback
-                                        .send(Err(anyhow::Error::msg(
-                                            "new transfer without filename",
-                                        )))
-                                        .ok();
-                                    return;
-                                }
+                        },
+                    },
+                    ftp_directive => ftp_directive,
+                };
 
-                                let filename = unsafe { filename.unwrap_unchecked() };
-                                let mut file = match stream.open(filename.as_ref()) {
-                                    Ok(file) => file,
-                                    Err(error) => {
-                                        callback.send(Err(error.into())).ok();
-                                        return;
-                                    }
-                                };
-
-                                let mut transfer_info = TransferInfo::default();
-
-                                match file.stat() {
-                                    Ok(stat) => {
-                                        let size = stat.size.unwrap_or(0);
-                                        if size == 0 {
-                                            // do not download files with size of 0
-                                            callback
-                                                .send(Err(anyhow::Error::msg(
-                                                    "invalid file size 0",
-                                                )))
-                                                .ok();
-                                            return;
-                                        }
-                                        transfer_info.file_size = size;
-                                    }
-                                    Err(error) => {
-                                        callback.send(Err(error.into())).ok();
-                                        return;
-                                    }
-                                }
+                let invalidation = ftp_directive.listing_invalidation();
+                if let Some(invalidation) = &invalidation {
+                    resources.listings.begin_write(invalidation);
+                }
+                let generation = resources.listings.generation();
+                let listing_id = id.clone();
+
+                // the command goes to the least busy session,
+                // another session is opened in the background if all of them are busy
+                let pool = ftp_clients.get(&id).unwrap();
+                if pool.begin_grow(false) {
+                    open_session(id.clone(), resources);
+                }
 
-                                transfer_info.file = Some(file);
-                                CachedValueBlocking::new(transfer_info)
-                            };
-
-                            let mut transfer_info_guard = transfer_info.write();
-
-                            let read_file =
-                                |guard: &mut RwLockWriteGuard<TransferInfo>,
-                                 buffer: &mut Vec<u8>| {
-                                    let file = guard.file.as_mut().unwrap();
-                                    file.read(buffer)
-                                };
-
-                            let mut buffer = vec![0u8; transfer_info_guard.chunk_size];
-                            match read_file(&mut transfer_info_guard, &mut buffer) {
-                                Ok(read_size) => {
-                                    buffer.drain(read_size..);
-                                    transfer_info_guard.total_read_size += read_size as u64;
-
-                                    let packs_left = (transfer_info_guard.file_size
-                                        / transfer_info_guard.chunk_size as u64)
-                                        - transfer_info_guard.chunks_sent as u64;
-                                    let content_pack = FileContentPack {
-                                        no: transfer_info_guard.chunks_sent as u64,
-                                        is_last: packs_left == 0 || buffer.is_empty(),
-                                        size: buffer.len() as u64,
-                                        bytes: buffer,
-                                        packs_left,
-                                    };
-
-                                    callback.send(Ok(content_pack)).ok();
-                                }
-                                Err(error) => {
-                                    callback.send(Err(error.into())).ok();
-                                    return;
-                                }
-                            }
+                // long commands wait for the worker, so they are driven from the dedicated threads
+                // or from the async tasks if they are paced by the client
+                if ftp_directive.is_driven() {
+                    let pool = pool.clone();
+                    worker::drive(move || {
+                        execute_driven(&pool, id, ftp_directive);
+                        end_write(listing_id, invalidation);
+                    });
+                } else if ftp_directive.is_streamed() {
+                    let pool = pool.clone();
+                    spawn(async move {
+                        execute_streamed(pool, id, ftp_directive).await;
+                        if let Some(invalidation) = invalidation {
+                            FtpSender::<NoCallback>::send(DirectiveFTP::EndWrite {
+                                id: listing_id,
+                                invalidation,
+                            })
+                            .await;
+                        }
+                    });
+                } else {
+                    pool.pick().submit(ftp_directive.priority(), move |stream| {
+                        execute(stream, id, ftp_directive, generation);
+                        end_write(listing_id, invalidation);
+                    });
+                }
+            }
 
-                            transfer_info_guard.chunks_sent += 1;
-                            let chunks_sent = transfer_info_guard.chunks_sent;
-                            transfer_info_guard.last_usage_timestamp = time::now();
+            DirectiveFTP::CacheListing {
+                id,
+                dir,
+                listing,
+                generation,
+            } => {
+                if let Some(resources) = ftp_resources.get_mut(&id) {
+                    resources
+                        .listings
+                        .insert(&dir, listing, generation, time::now());
+                }
+            }
 
-                            drop(transfer_info_guard);
-                            if chunks_sent == 1 {
-                                Cache::add_transfer_info_blocking(transfer_id, transfer_info);
-                            }
-                        }
-                    }
-                });
+            DirectiveFTP::EndWrite { id, invalidation } => {
+                if let Some(resources) = ftp_resources.get_mut(&id) {
+                    resources.listings.end_write(&invalidation);
+                }
             }
 
             DirectiveFTP::Clean { now } => {
                 ftp_resources.retain(|id, resources| {
+                    resources.listings.clean(now);
+                    if let Some(pool) = ftp_clients.get(id) {
+                        pool.shrink(now);
+                    }
                     if resources.in_usage {
                         return true;
                     }
 
                     if now - resources.last_usage_timestamp >= FTP_CLIENT_LIFETIME_S {
                         debug!("Removing ftp client ({id})");
-                        ftp_clients.remove(id);
+                        if let Some(pool) = ftp_clients.remove(id) {
+                            spawn(close_client(id.clone(), pool));
+                        }
                         return false;
                     }
 
                     true
                 });
             }
+
+            // Remove old entities from the trash of every connected client
+            DirectiveFTP::CleanTrash { now } => {
+                let deleted_before = now - CONFIG.trash_max_age_s;
+
+                for (id, pool) in &ftp_clients {
+                    let id = id.clone();
+
+                    if let Some(resources) = ftp_resources.get_mut(&id) {
+                        resources.listings.begin_write(&ListingInvalidation::All);
+                    }
+
+                    pool.pick().submit(Priority::Bulk, move |stream| {
+                        match ftp::purge_trash(stream, None, Some(deleted_before)) {
+                            Ok(0) => {}
+                            Ok(purged) => debug!("Purged {purged} trash entities of ({id})"),
+                            Err(error) => warn!("Cannot purge trash of ({id}) because {error}"),
+                        }
+
+                        FtpSender::<NoCallback>::send_blocking(DirectiveFTP::EndWrite {
+                            id,
+                            invalidation: ListingInvalidation::All,
+                        });
+                    });
+                }
+            }
         }
 
         // todo: implement cleanup code
@@ -333,3 +352,496 @@ pub async fn handler(mut rx: Receiver<DirectiveFTP>) {
         */
     }
 }
+
+/// Executes the command as a single job of the session worker
+fn execute(stream: &Sftp, id: FtpClientID, ftp_directive: DirectiveExecuteFTP, generation: u64) {
+    match ftp_directive {
+        // List all entities in the dir
+        DirectiveExecuteFTP::ReadDir { dir, callback, .. } => {
+            let listing = stream.readdir(dir.as_ref());
+
+            // the listing is cached before it's returned
+            if let Ok(listing) = &listing {
+                FtpSender::<NoCallback>::send_blocking(DirectiveFTP::CacheListing {
+                    id: id.clone(),
+                    dir,
+                    listing: listing.clone(),
+                    generation,
+                });
+            }
+            callback.send(listing.map_err(|e| e.into())).ok();
+        }
+
+        // Compare two text files
+        DirectiveExecuteFTP::DiffFiles {
+            left,
+            right,
+            ignore_whitespace,
+            context,
+            callback,
+        } => {
+            callback
+                .send(ftp::diff_files(
+                    stream,
+                    left,
+                    right,
+                    ignore_whitespace,
+                    context,
+                ))
+                .ok();
+        }
+
+        // Walk and compare two directory trees
+        DirectiveExecuteFTP::CompareDirs {
+            left,
+            right,
+            hash,
+            callback,
+        } => {
+            callback
+                .send(ftp::compare_dirs(stream, left, right, hash))
+                .ok();
+        }
+
+        // Execute multiple operations in order
+        DirectiveExecuteFTP::Batch {
+            operations,
+            stop_on_error,
+            callback,
+        } => {
+            callback
+                .send(Ok(ftp::execute_batch(stream, operations, stop_on_error)))
+                .ok();
+        }
+
+        // List entities in the user's trash
+        DirectiveExecuteFTP::ListTrash { callback } => {
+            callback.send(ftp::list_trash(stream)).ok();
+        }
+
+        // Move entity from the trash back to its place
+        DirectiveExecuteFTP::RestoreTrash {
+            trash_id,
+            to,
+            callback,
+        } => {
+            callback
+                .send(ftp::restore_from_trash(stream, &trash_id, to))
+                .ok();
+        }
+
+        // Permanently remove entities from the trash
+        DirectiveExecuteFTP::PurgeTrash { trash_id, callback } => {
+            callback
+                .send(ftp::purge_trash(stream, trash_id.as_deref(), None))
+                .ok();
+        }
+
+        // List previous versions of the file
+        DirectiveExecuteFTP::ListVersions { file, callback } => {
+            callback
+                .send(ftp::list_versions(stream, file.as_ref()))
+                .ok();
+        }
+
+        // Replace the file with one of its previous versions
+        DirectiveExecuteFTP::RestoreVersion {
+            file,
+            version,
+            callback,
+        } => {
+            callback
+                .send(ftp::restore_version(stream, file.as_ref(), &version))
+                .ok();
+        }
+
+        // Stat the file through the opened handle, so the read permission is checked as well
+        DirectiveExecuteFTP::OpenStat { file, callback } => {
+            let stat = stream.open(file.as_ref()).and_then(|mut file| file.stat());
+            callback.send(stat.map_err(|e| e.into())).ok();
+        }
+
+        // Create the temporary file of the resumable upload
+        DirectiveExecuteFTP::CreateUpload {
+            temp_file,
+            callback,
+        } => {
+            callback
+                .send(ftp::create_upload(stream, temp_file.as_ref()))
+                .ok();
+        }
+
+        // Replace the destination with the complete upload
+        DirectiveExecuteFTP::FinishUpload {
+            temp_file,
+            file,
+            callback,
+        } => {
+            callback
+                .send(ftp::finish_upload(
+                    stream,
+                    temp_file.as_ref(),
+                    file.as_ref(),
+                ))
+                .ok();
+        }
+
+        DirectiveExecuteFTP::DiscardUpload {
+            temp_file,
+            callback,
+        } => {
+            callback
+                .send(ftp::discard_upload(stream, temp_file.as_ref()))
+                .ok();
+        }
+
+        _ => unreachable!("driven commands are not queued as a single job"),
+    }
+}
+
+/// Executes the long running command, every file operation is queued on the worker separately
+fn execute_driven(pool: &SessionPool, id: FtpClientID, ftp_directive: DirectiveExecuteFTP) {
+    let worker = &pool.pick();
+    match ftp_directive {
+        // Start or continue the file transfer
+        DirectiveExecuteFTP::TransferFile {
+            transfer_id,
+            filename,
+            chunk_size,
+            pack,
+            callback,
+        } => {
+            let transfer_info = if let Some(transfer_info) =
+                Cache::get_transfer_info_blocking(transfer_id.clone())
+            {
+                transfer_info
+            } else {
+                if filename.is_none() {
+                    callback
+                        .send(Err(anyhow::Error::msg("transfer not found or expired")))
+                        .ok();
+                    return;
+                }
+
+                let max_transfers = CONFIG.transfer_max_per_client;
+                if max_transfers > 0
+                    && Cache::list_transfers_blocking(id.clone()).len() >= max_transfers
+                {
+                    callback
+                        .send(Err(anyhow::Error::msg("too many active transfers")))
+                        .ok();
+                    return;
+                }
+
+                let filename = unsafe { filename.unwrap_unchecked() };
+                let file =
+                    match SessionFile::open_blocking(worker, filename.as_ref(), Priority::Bulk) {
+                        Ok(file) => file,
+                        Err(error) => {
+                            callback.send(Err(error)).ok();
+                            return;
+                        }
+                    };
+
+                let mut transfer_info = TransferInfo {
+                    path: filename,
+                    ..Default::default()
+                };
+                if let Some(chunk_size) = chunk_size {
+                    transfer_info.set_chunk_size(chunk_size);
+                }
+
+                let modified = match file.stat_blocking() {
+                    Ok(stat) => {
+                        let size = stat.size.unwrap_or(0);
+                        if size == 0 {
+                            // do not download files with size of 0
+                            callback
+                                .send(Err(anyhow::Error::msg("invalid file size 0")))
+                                .ok();
+                            return;
+                        }
+                        transfer_info.file_size = size;
+                        stat.mtime.unwrap_or(0) as i64
+                    }
+                    Err(error) => {
+                        callback.send(Err(error)).ok();
+                        return;
+                    }
+                };
+
+                // files in the shared roots are read from the disk cache if possible
+                let local = if disk::is_cached_path(&transfer_info.path) {
+                    Cache::disk_cache_lookup_blocking(
+                        id.clone(),
+                        &transfer_info.path,
+                        transfer_info.file_size,
+                        modified,
+                    )
+                    .and_then(|local| std::fs::File::open(local).ok())
+                } else {
+                    None
+                };
+
+                let chunk_size = transfer_info.chunk_size;
+                let source = match local {
+                    Some(local) => TransferSource::Local(local),
+                    None => TransferSource::Remote(file),
+                };
+                transfer_info.reader = Some(PrefetchReader::new(source, chunk_size));
+                CachedValueBlocking::new(transfer_info)
+            };
+
+            let mut transfer_info_guard = transfer_info.write();
+            let packs_sent = transfer_info_guard.packs.len() as u64;
+
+            // packs which have been sent already are read again from the file
+            match pack {
+                Some(no) if no < packs_sent => {
+                    let (offset, size) = transfer_info_guard.packs[no as usize];
+                    let content_pack =
+                        ftp::read_pack(worker, transfer_info_guard.path.as_ref(), offset, size)
+                            .map(|bytes| {
+                                let is_last = transfer_info_guard.is_last_pack(no);
+                                FileContentPack {
+                                    no,
+                                    is_last,
+                                    size: bytes.len() as u64,
+                                    checksum: ftp::pack_checksum(&bytes),
+                                    bytes,
+                                    packs_left: transfer_info_guard.packs_left(),
+                                    chunk_size: transfer_info_guard.chunk_size,
+                                    transfer: transfer_id.token().to_string(),
+                                    file_checksum: transfer_info_guard
+                                        .file_digest
+                                        .clone()
+                                        .filter(|_| is_last),
+                                }
+                            });
+
+                    transfer_info_guard.last_usage_timestamp = time::now();
+                    drop(transfer_info_guard);
+                    callback.send(content_pack).ok();
+                    return;
+                }
+                Some(no) if no > packs_sent => {
+                    callback
+                        .send(Err(anyhow::Error::msg("pack has not been sent yet")))
+                        .ok();
+                    return;
+                }
+                _ => {}
+            }
+
+            // the reader is dropped when the transfer gets cancelled or finished
+            let finished = transfer_info_guard.file_digest.is_some();
+            let chunk = match transfer_info_guard.reader.as_mut() {
+                Some(reader) => reader.next_chunk(),
+                None if finished => Err(std::io::Error::other("transfer has already finished")),
+                None => Err(std::io::Error::other("transfer has been cancelled")),
+            };
+
+            match chunk {
+                Ok((buffer, read_time)) => {
+                    let read_size = buffer.len();
+                    let offset = transfer_info_guard.total_read_size;
+                    transfer_info_guard.total_read_size += read_size as u64;
+                    transfer_info_guard.packs.push((offset, read_size));
+                    transfer_info_guard.digest.update(&buffer);
+
+                    transfer_info_guard.adapt_chunk_size(read_size, read_time);
+                    let chunk_size = transfer_info_guard.chunk_size;
+                    if let Some(reader) = transfer_info_guard.reader.as_ref() {
+                        reader.set_chunk_size(chunk_size);
+                    }
+
+                    let packs_left = transfer_info_guard.packs_left();
+                    let is_last = packs_left == 0 || buffer.is_empty();
+
+                    // the finished transfer is kept until it expires,
+                    // so its packs can still be requested again
+                    if is_last {
+                        transfer_info_guard.reader = None;
+                        transfer_info_guard.file_digest = Some(format!(
+                            "{:x}",
+                            transfer_info_guard.digest.clone().finalize()
+                        ));
+                    }
+
+                    let content_pack = FileContentPack {
+                        no: transfer_info_guard.chunks_sent as u64,
+                        is_last,
+                        size: buffer.len() as u64,
+                        checksum: ftp::pack_checksum(&buffer),
+                        bytes: buffer,
+                        packs_left,
+                        chunk_size: transfer_info_guard.chunk_size,
+                        transfer: transfer_id.token().to_string(),
+                        file_checksum: transfer_info_guard.file_digest.clone(),
+                    };
+
+                    transfer_info_guard.chunks_sent += 1;
+                    transfer_info_guard.last_usage_timestamp = time::now();
+
+                    // the transfer is cached before the pack is returned,
+                    // so the next request with the token can find it
+                    if transfer_info_guard.chunks_sent == 1 {
+                        Cache::add_transfer_info_blocking(
+                            transfer_id.clone(),
+                            transfer_info.clone(),
+                        );
+                    }
+
+                    // the transfer is not locked while the pack waits for the rate limit
+                    drop(transfer_info_guard);
+                    Limiter::new(Direction::Download, Some(id)).consume_blocking(read_size as u64);
+                    callback.send(Ok(content_pack)).ok();
+                }
+                Err(error) => {
+                    callback.send(Err(error.into())).ok();
+                }
+            }
+        }
+
+        // Copy the file into the local file system
+        DirectiveExecuteFTP::SnapshotFile {
+            file,
+            destination,
+            max_size,
+            callback,
+        } => {
+            callback
+                .send(ftp::snapshot_file(
+                    worker,
+                    file.as_ref(),
+                    &destination,
+                    max_size,
+                ))
+                .ok();
+        }
+
+        _ => unreachable!("only the driven commands are executed here"),
+    }
+}
+
+/// Opens the additional session of the client in the background
+fn open_session(id: FtpClientID, resources: &Resources) {
+    let login_data = resources.login_data.clone();
+    tokio::task::spawn_blocking(move || {
+        let connection = ftp::connect_additional(&login_data);
+        FtpSender::<NoCallback>::send_blocking(DirectiveFTP::SFTPAddSession { id, connection });
+    });
+}
+
+/// Discards the idle uploads of the removed client through its sessions and closes them
+async fn close_client(id: FtpClientID, pool: SessionPool) {
+    let temp_paths: Vec<String> = Cache::upload_take_client(id.clone())
+        .await
+        .into_iter()
+        .map(|upload_info| upload_info.temp_path)
+        .collect();
+
+    if !temp_paths.is_empty() {
+        pool.pick().submit(Priority::Normal, move |stream| {
+            let leftovers: Vec<String> = temp_paths
+                .into_iter()
+                .filter(|temp_path| ftp::discard_upload(stream, temp_path.as_ref()).is_err())
+                .collect();
+            if !leftovers.is_empty() {
+                Cache::upload_add_leftovers_blocking(id, leftovers);
+            }
+        });
+    }
+
+    // the queued commands are executed before the sessions stop
+    pool.close();
+}
+
+/// Removes the temporary files left by the uploads of the previous login
+async fn discard_leftovers(id: FtpClientID) {
+    for temp_path in Cache::upload_take_leftovers(id.clone()).await {
+        Cache::ftp_discard_upload(id.clone(), temp_path).await.ok();
+    }
+}
+
+/// Executes the command paced by the client,
+/// every file operation is queued on the worker separately and awaited
+#[allow(deprecated)]
+async fn execute_streamed(pool: SessionPool, id: FtpClientID, ftp_directive: DirectiveExecuteFTP) {
+    match ftp_directive {
+        // Stream file content [deprecated]
+        DirectiveExecuteFTP::ReadFile { file, callback } => {
+            // try to open the file
+            let file = match SessionFile::open(&pool.pick(), file.as_ref(), Priority::Bulk).await {
+                Ok(file) => file,
+                Err(error) => {
+                    callback.send(Err(error)).ok();
+                    return;
+                }
+            };
+
+            // create an mpsc channel for streaming file bytes and return the sender in the callback
+            let (file_tx, mut file_rx) =
+                mpsc::channel::<(usize, oneshot::Sender<anyhow::Result<Vec<u8>>>)>(32);
+            callback.send(Ok(file_tx)).ok();
+
+            // stream bytes of the file
+            while let Some((buffer_size, callback)) = file_rx.recv().await {
+                match file.read_chunk(buffer_size).await {
+                    Ok(buffer) => {
+                        let is_end = buffer.is_empty();
+                        if callback.send(Ok(buffer)).is_err() || is_end {
+                            return;
+                        }
+                    }
+                    Err(error) => {
+                        callback.send(Err(error.into())).ok();
+                        return;
+                    }
+                }
+            }
+        }
+
+        // Stream the file content or its range, returns once the stream is finished
+        DirectiveExecuteFTP::StreamFile {
+            file,
+            request,
+            callback,
+        } => {
+            let limiter = Limiter::new(Direction::Download, Some(id.clone()));
+            ftp::stream_file(pool, id, file.as_ref(), request, limiter, callback).await;
+        }
+
+        // Write the upload content, returns once all the chunks are received
+        DirectiveExecuteFTP::WriteUpload {
+            temp_file,
+            offset,
+            chunks,
+            callback,
+        } => {
+            let written = ftp::write_upload(
+                &pool.pick(),
+                temp_file.as_ref(),
+                offset,
+                chunks,
+                Limiter::new(Direction::Upload, Some(id)),
+            )
+            .await;
+            callback.send(written).ok();
+        }
+
+        _ => unreachable!("only the streamed commands are executed here"),
+    }
+}
+
+/// Name of the worker thread, only the beginning of the client id is used
+fn worker_name(id: &FtpClientID) -> String {
+    id.chars().take(8).collect()
+}
+
+/// Ends the write operation, so the listings can be cached again
+fn end_write(id: FtpClientID, invalidation: Option<ListingInvalidation>) {
+    if let Some(invalidation) = invalidation {
+        FtpSender::<NoCallback>::send_blocking(DirectiveFTP::EndWrite { id, invalidation });
+    }
+}
@@ -1,10 +1,17 @@
 // todo: combine both senders into one
+pub mod disk_cache;
 pub mod ftp_cache;
+pub mod share_cache;
 pub mod transfer_cache;
+pub mod upload_cache;
 
-use crate::cache::{Cache, FTP_CLEANING_DELAY_S, TRANSFER_CLEANING_DELAY_S};
+use crate::cache::{
+    Cache, FTP_CLEANING_DELAY_S, SHARE_CLEANING_DELAY_S, TRANSFER_CLEANING_DELAY_S,
+    TRASH_CLEANING_DELAY_S, UPLOAD_CLEANING_DELAY_S,
+};
 use crate::info;
 use crate::utils::time;
+use crate::CONFIG;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 lazy_static! {
@@ -23,6 +30,9 @@ pub async fn cleanup() {
         tokio::spawn(async move {
             let mut last_cleanup_ftp = time::now();
             let mut last_cleanup_transfer = last_cleanup_ftp;
+            let mut last_cleanup_trash = last_cleanup_ftp;
+            let mut last_cleanup_share = last_cleanup_ftp;
+            let mut last_cleanup_upload = last_cleanup_ftp;
 
             while { !super::SENDER_FTP.is_closed() || !super::SENDER_TRANSFER.is_closed() } {
                 let now = time::now();
@@ -36,6 +46,21 @@ pub async fn cleanup() {
                     Cache::clean_transfers(now).await;
                     last_cleanup_transfer = now;
                 }
+
+                if CONFIG.trash_enabled && now - last_cleanup_trash >= TRASH_CLEANING_DELAY_S {
+                    Cache::clean_trash(now).await;
+                    last_cleanup_trash = now;
+                }
+
+                if now - last_cleanup_share >= SHARE_CLEANING_DELAY_S {
+                    Cache::clean_shares(now).await;
+                    last_cleanup_share = now;
+                }
+
+                if now - last_cleanup_upload >= UPLOAD_CLEANING_DELAY_S {
+                    Cache::clean_uploads(now).await;
+                    last_cleanup_upload = now;
+                }
             }
         });
     }
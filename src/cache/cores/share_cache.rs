@@ -0,0 +1,96 @@
+use super::super::*;
+use crate::cache::share::{self, ShareLink};
+use crate::CONFIG;
+
+/// All the share link related directives are processed here
+pub async fn handler(mut rx: Receiver<DirectiveShare>) {
+    let mut links: HashMap<String, ShareLink> = tokio::task::spawn_blocking(share::load_links)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|link| (link.id.clone(), link))
+        .collect();
+
+    let remove_files = |ids: Vec<String>| {
+        if !ids.is_empty() {
+            tokio::task::spawn_blocking(move || {
+                ids.iter().for_each(|id| share::remove_link_files(id))
+            });
+        }
+    };
+
+    while let Some(directive) = rx.recv().await {
+        match directive {
+            // Returns number of the owner's links and the size of all the snapshots
+            DirectiveShare::Usage { owner, callback } => {
+                let count = links.values().filter(|link| link.owner == owner).count();
+                let total_size = links.values().map(|link| link.size).sum();
+                callback.send((count, total_size)).ok();
+            }
+
+            // Registers the link if it fits in the limits
+            DirectiveShare::Add { link, callback } => {
+                let count = links
+                    .values()
+                    .filter(|other| other.owner == link.owner)
+                    .count();
+                let total_size: u64 = links.values().map(|link| link.size).sum();
+
+                if count >= CONFIG.share_max_links_per_user {
+                    callback
+                        .send(Err(anyhow::Error::msg("too many active share links")))
+                        .ok();
+                } else if total_size + link.size > CONFIG.share_max_total_size {
+                    callback
+                        .send(Err(anyhow::Error::msg("share storage is full")))
+                        .ok();
+                } else {
+                    links.insert(link.id.clone(), link);
+                    callback.send(Ok(())).ok();
+                }
+            }
+
+            DirectiveShare::Get { id, callback } => {
+                callback.send(links.get(&id).cloned()).ok();
+            }
+
+            DirectiveShare::List { owner, callback } => {
+                let mut owned: Vec<ShareLink> = links
+                    .values()
+                    .filter(|link| link.owner == owner)
+                    .cloned()
+                    .collect();
+                owned.sort_by_key(|link| std::cmp::Reverse(link.created_at));
+                callback.send(owned).ok();
+            }
+
+            // Only the owner can revoke the link
+            DirectiveShare::Revoke {
+                owner,
+                id,
+                callback,
+            } => {
+                let is_owner = links.get(&id).map(|link| link.owner == owner) == Some(true);
+                if is_owner {
+                    links.remove(&id);
+                    remove_files(vec![id]);
+                }
+                callback.send(is_owner).ok();
+            }
+
+            DirectiveShare::Clean { now } => {
+                let expired: Vec<String> = links
+                    .values()
+                    .filter(|link| link.expires_at <= now)
+                    .map(|link| link.id.clone())
+                    .collect();
+
+                for id in &expired {
+                    debug!("Removing expired share link ({id})");
+                    links.remove(id);
+                }
+                remove_files(expired);
+            }
+        }
+    }
+}
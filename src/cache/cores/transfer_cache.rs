@@ -1,4 +1,6 @@
 use super::super::*;
+use crate::cache::ftp::TransferStatus;
+use crate::utils::time;
 
 /// Used to simplify sending directives to the cache
 pub struct TransferSender<T> {
@@ -76,6 +78,43 @@ pub async fn handler(mut rx: Receiver<DirectiveTransfer>) {
                 transfers.remove(&transfer_id);
             }
 
+            // Removes the transfer and stops its reader,
+            // the file handle is dropped after the read in progress is finished
+            DirectiveTransfer::CancelTransfer {
+                transfer_id,
+                callback,
+            } => {
+                let transfer_info = transfers.remove(&transfer_id);
+                callback.send(transfer_info.is_some()).ok();
+
+                if let Some(transfer_info) = transfer_info {
+                    tokio::task::spawn_blocking(move || {
+                        transfer_info.write().reader = None;
+                    });
+                }
+            }
+
+            DirectiveTransfer::ListTransfers { owner, callback } => {
+                let owned: Vec<(TransferID, CachedValueBlocking<TransferInfo>)> = transfers
+                    .iter()
+                    .filter(|(id, _)| id.owner() == &owner)
+                    .map(|(id, info)| (id.clone(), info.clone()))
+                    .collect();
+
+                tokio::task::spawn_blocking(move || {
+                    let now = time::now();
+                    // finished transfers are only kept for requesting the packs again
+                    let mut statuses: Vec<TransferStatus> = owned
+                        .iter()
+                        .map(|(id, info)| (id, info.read()))
+                        .filter(|(_, info)| info.file_digest.is_none())
+                        .map(|(id, info)| TransferStatus::new(id, &info, now))
+                        .collect();
+                    statuses.sort_by_key(|status| status.started_at);
+                    callback.send(statuses).ok();
+                });
+            }
+
             DirectiveTransfer::Clean { now } => {
                 let t_vec: Vec<(TransferID, CachedValueBlocking<TransferInfo>)> = transfers
                     .iter()
@@ -84,7 +123,7 @@ pub async fn handler(mut rx: Receiver<DirectiveTransfer>) {
 
                 tokio::task::spawn_blocking(move || {
                     for (id, info) in t_vec {
-                        if info.read().last_usage_timestamp >= TRANSFER_LIFETIME_S {
+                        if now - info.read().last_usage_timestamp >= TRANSFER_LIFETIME_S {
                             Cache::remove_transfer_blocking(id);
                         }
                     }
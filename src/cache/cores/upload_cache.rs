@@ -0,0 +1,112 @@
+use super::super::*;
+use crate::cache::ftp::UploadInfo;
+use crate::utils::time;
+use crate::CONFIG;
+
+/// All the resumable upload related directives are processed here
+pub async fn handler(mut rx: Receiver<DirectiveUpload>) {
+    let mut uploads = HashMap::<TransferID, UploadInfo>::new();
+    let mut leftovers = HashMap::<FtpClientID, Vec<String>>::new();
+
+    while let Some(directive) = rx.recv().await {
+        match directive {
+            DirectiveUpload::AddUploadInfo {
+                upload_id,
+                upload_info,
+            } => {
+                uploads.insert(upload_id, upload_info);
+            }
+
+            DirectiveUpload::GetUploadInfo {
+                upload_id,
+                callback,
+            } => {
+                callback.send(uploads.get(&upload_id).cloned()).ok();
+            }
+
+            DirectiveUpload::AcquireUpload {
+                upload_id,
+                callback,
+            } => {
+                let upload_info = uploads.get_mut(&upload_id).map(|upload_info| {
+                    let previous = upload_info.clone();
+                    upload_info.busy = true;
+                    upload_info.last_usage_timestamp = time::now();
+                    previous
+                });
+                callback.send(upload_info).ok();
+            }
+
+            DirectiveUpload::ReleaseUpload { upload_id, offset } => {
+                if let Some(upload_info) = uploads.get_mut(&upload_id) {
+                    upload_info.busy = false;
+                    upload_info.offset = offset;
+                    upload_info.last_usage_timestamp = time::now();
+                }
+            }
+
+            DirectiveUpload::RemoveUploadInfo { upload_id } => {
+                uploads.remove(&upload_id);
+            }
+
+            DirectiveUpload::TakeClientUploads { owner, callback } => {
+                let taken: Vec<TransferID> = uploads
+                    .iter()
+                    .filter(|(upload_id, upload_info)| {
+                        upload_id.owner() == &owner && !upload_info.busy
+                    })
+                    .map(|(upload_id, _)| upload_id.clone())
+                    .collect();
+
+                let taken = taken
+                    .iter()
+                    .filter_map(|upload_id| uploads.remove(upload_id))
+                    .collect();
+                callback.send(taken).ok();
+            }
+
+            DirectiveUpload::AddLeftovers { owner, temp_paths } => {
+                leftovers.entry(owner).or_default().extend(temp_paths);
+            }
+
+            DirectiveUpload::TakeLeftovers { owner, callback } => {
+                callback
+                    .send(leftovers.remove(&owner).unwrap_or_default())
+                    .ok();
+            }
+
+            // Abandoned uploads are removed together with their temporary files,
+            // the files of the logged-off clients are removed on their next login
+            DirectiveUpload::Clean { now } => {
+                let expired: Vec<TransferID> = uploads
+                    .iter()
+                    .filter(|(_, upload_info)| {
+                        !upload_info.busy
+                            && now - upload_info.last_usage_timestamp >= CONFIG.upload_expiration_s
+                    })
+                    .map(|(upload_id, _)| upload_id.clone())
+                    .collect();
+
+                for upload_id in expired {
+                    if let Some(upload_info) = uploads.remove(&upload_id) {
+                        debug!("Removing expired upload ({})", upload_info.path);
+                        spawn(discard_expired(upload_id, upload_info));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Removes the temporary file of the expired upload
+async fn discard_expired(upload_id: TransferID, upload_info: UploadInfo) {
+    let owner = upload_id.owner().clone();
+    let discarded = Cache::ftp_client_exists(owner.as_str()).await
+        && Cache::ftp_discard_upload(owner.clone(), &upload_info.temp_path)
+            .await
+            .is_ok();
+
+    if !discarded {
+        Cache::upload_add_leftovers(owner, vec![upload_info.temp_path]).await;
+    }
+}
@@ -1,5 +1,13 @@
 use crate::cache::cached_value::CachedValueBlocking;
-use crate::cache::ftp::{FileContentPack, TransferID, TransferInfo};
+use crate::cache::disk::DiskCacheLookup;
+use crate::cache::ftp::{
+    BatchOperation, BatchReport, ChunkSize, ConnectionSlot, DirComparison, FileContentPack,
+    FileDiff, FileStream, StreamRequest, TransferID, TransferInfo, TransferStatus, TrashEntry,
+    UploadInfo, VersionEntry,
+};
+use crate::cache::listing::{versions_dir, DirListing, ListingInvalidation};
+use crate::cache::share::ShareLink;
+use crate::cache::worker::Priority;
 use crate::cache::FtpClientID;
 use crate::handler::endpoints::login::LoginData;
 use ssh2::{FileStat, Sftp};
@@ -18,6 +26,21 @@ pub enum DirectiveFTP {
     SFTPAddClient {
         id: FtpClientID,
         stream: Sftp,
+        slot: ConnectionSlot,
+        login_data: LoginData,
+    },
+    /// Requests the additional session for the large segmented stream
+    SFTPRequestSession {
+        id: FtpClientID,
+    },
+    /// Additional session of the client opened by the pool
+    SFTPAddSession {
+        id: FtpClientID,
+        connection: anyhow::Result<(Sftp, ConnectionSlot)>,
+    },
+    SFTPCredentials {
+        id: FtpClientID,
+        callback: Callback<Option<LoginData>>,
     },
     SFTPClientExists {
         id_raw: String,
@@ -31,6 +54,21 @@ pub enum DirectiveFTP {
     Clean {
         now: i64,
     },
+    CleanTrash {
+        now: i64,
+    },
+    /// Private directive, stores the listing in the client's listing cache
+    CacheListing {
+        id: FtpClientID,
+        dir: String,
+        listing: DirListing,
+        generation: u64,
+    },
+    /// Private directive, called when the write operation has finished
+    EndWrite {
+        id: FtpClientID,
+        invalidation: ListingInvalidation,
+    },
 }
 
 /// File Transfer related commands
@@ -46,16 +84,70 @@ pub enum DirectiveTransfer {
     RemoveTransferInfo {
         transfer_id: TransferID,
     },
+    CancelTransfer {
+        transfer_id: TransferID,
+        callback: Callback<bool>,
+    },
+    ListTransfers {
+        owner: FtpClientID,
+        callback: Callback<Vec<TransferStatus>>,
+    },
     Clean {
         now: i64,
     },
 }
 
+/// Share link related commands
+pub enum DirectiveShare {
+    Usage {
+        owner: String,
+        callback: Callback<(usize, u64)>,
+    },
+    Add {
+        link: ShareLink,
+        callback: Callback<anyhow::Result<()>>,
+    },
+    Get {
+        id: String,
+        callback: Callback<Option<ShareLink>>,
+    },
+    List {
+        owner: String,
+        callback: Callback<Vec<ShareLink>>,
+    },
+    Revoke {
+        owner: String,
+        id: String,
+        callback: Callback<bool>,
+    },
+    Clean {
+        now: i64,
+    },
+}
+
+/// Disk cache related commands
+pub enum DirectiveDiskCache {
+    Lookup {
+        key: String,
+        size: u64,
+        callback: Callback<DiskCacheLookup>,
+    },
+    Insert {
+        key: String,
+        size: u64,
+    },
+    Abort {
+        key: String,
+    },
+}
+
 /// Commands representing what ftp operation to perform
 pub enum DirectiveExecuteFTP {
     ReadDir {
         dir: String,
-        callback: Callback<anyhow::Result<Vec<(PathBuf, FileStat)>>>,
+        /// skips the listing cache
+        refresh: bool,
+        callback: Callback<anyhow::Result<DirListing>>,
     },
     #[deprecated]
     ReadFile {
@@ -67,6 +159,193 @@ pub enum DirectiveExecuteFTP {
     TransferFile {
         transfer_id: TransferID,
         filename: Option<String>,
+        chunk_size: Option<ChunkSize>,
+        pack: Option<u64>,
         callback: Callback<anyhow::Result<FileContentPack>>,
     },
+    DiffFiles {
+        left: String,
+        right: String,
+        ignore_whitespace: bool,
+        context: usize,
+        callback: Callback<anyhow::Result<FileDiff>>,
+    },
+    CompareDirs {
+        left: String,
+        right: String,
+        hash: bool,
+        callback: Callback<anyhow::Result<DirComparison>>,
+    },
+    Batch {
+        operations: Vec<BatchOperation>,
+        stop_on_error: bool,
+        callback: Callback<anyhow::Result<BatchReport>>,
+    },
+    ListTrash {
+        callback: Callback<anyhow::Result<Vec<TrashEntry>>>,
+    },
+    RestoreTrash {
+        trash_id: String,
+        to: Option<String>,
+        callback: Callback<anyhow::Result<TrashEntry>>,
+    },
+    PurgeTrash {
+        trash_id: Option<String>,
+        callback: Callback<anyhow::Result<usize>>,
+    },
+    ListVersions {
+        file: String,
+        callback: Callback<anyhow::Result<Vec<VersionEntry>>>,
+    },
+    RestoreVersion {
+        file: String,
+        version: String,
+        callback: Callback<anyhow::Result<VersionEntry>>,
+    },
+    SnapshotFile {
+        file: String,
+        destination: PathBuf,
+        max_size: u64,
+        callback: Callback<anyhow::Result<u64>>,
+    },
+    /// the handle is dropped right after the stat
+    OpenStat {
+        file: String,
+        callback: Callback<anyhow::Result<FileStat>>,
+    },
+    StreamFile {
+        file: String,
+        request: StreamRequest,
+        callback: Callback<anyhow::Result<FileStream>>,
+    },
+    CreateUpload {
+        temp_file: String,
+        callback: Callback<anyhow::Result<()>>,
+    },
+    WriteUpload {
+        temp_file: String,
+        offset: u64,
+        chunks: mpsc::Receiver<Vec<u8>>,
+        callback: Callback<anyhow::Result<u64>>,
+    },
+    FinishUpload {
+        temp_file: String,
+        file: String,
+        callback: Callback<anyhow::Result<()>>,
+    },
+    DiscardUpload {
+        temp_file: String,
+        callback: Callback<anyhow::Result<()>>,
+    },
+}
+
+impl DirectiveExecuteFTP {
+    /// Listings changed by the operation, None if the operation doesn't modify any directory
+    pub fn listing_invalidation(&self) -> Option<ListingInvalidation> {
+        match self {
+            // deleted entities can be moved into the trash
+            Self::Batch { operations, .. }
+                if operations
+                    .iter()
+                    .any(|operation| matches!(operation, BatchOperation::Delete { .. })) =>
+            {
+                Some(ListingInvalidation::All)
+            }
+            Self::Batch { operations, .. } => Some(ListingInvalidation::paths(
+                operations.iter().flat_map(|operation| match operation {
+                    BatchOperation::Mkdir { path } => vec![PathBuf::from(path)],
+                    BatchOperation::Move { from, to, .. } => {
+                        vec![PathBuf::from(from), PathBuf::from(to), versions_dir(to)]
+                    }
+                    BatchOperation::Delete { .. } => Vec::new(),
+                }),
+            )),
+            Self::RestoreTrash { .. } | Self::PurgeTrash { .. } => Some(ListingInvalidation::All),
+            Self::RestoreVersion { file, .. } => Some(ListingInvalidation::paths([
+                PathBuf::from(file),
+                versions_dir(file),
+            ])),
+            Self::CreateUpload { temp_file, .. } | Self::DiscardUpload { temp_file, .. } => {
+                Some(ListingInvalidation::paths([temp_file]))
+            }
+            Self::FinishUpload {
+                temp_file, file, ..
+            } => Some(ListingInvalidation::paths([
+                PathBuf::from(temp_file),
+                PathBuf::from(file),
+                versions_dir(file),
+            ])),
+            _ => None,
+        }
+    }
+
+    /// Position of the command in the queue of the session worker
+    pub fn priority(&self) -> Priority {
+        match self {
+            Self::ReadDir { .. }
+            | Self::OpenStat { .. }
+            | Self::ListTrash { .. }
+            | Self::ListVersions { .. } => Priority::Interactive,
+            Self::CompareDirs { .. } => Priority::Bulk,
+            _ if self.is_driven() || self.is_streamed() => Priority::Bulk,
+            _ => Priority::Normal,
+        }
+    }
+
+    /// Long running commands which are not executed as a single job of the session worker,
+    /// they queue every read or write of the file separately from the dedicated thread instead
+    pub fn is_driven(&self) -> bool {
+        matches!(self, Self::TransferFile { .. } | Self::SnapshotFile { .. })
+    }
+
+    /// Long running commands paced by the client, they are executed as the async tasks,
+    /// so the client which stopped reading or sending doesn't hold any thread
+    #[allow(deprecated)]
+    pub fn is_streamed(&self) -> bool {
+        matches!(
+            self,
+            Self::ReadFile { .. } | Self::StreamFile { .. } | Self::WriteUpload { .. }
+        )
+    }
+}
+
+/// Resumable upload related commands
+pub enum DirectiveUpload {
+    AddUploadInfo {
+        upload_id: TransferID,
+        upload_info: UploadInfo,
+    },
+    GetUploadInfo {
+        upload_id: TransferID,
+        callback: Callback<Option<UploadInfo>>,
+    },
+    /// Marks the upload as busy, returns its state from before the call
+    AcquireUpload {
+        upload_id: TransferID,
+        callback: Callback<Option<UploadInfo>>,
+    },
+    ReleaseUpload {
+        upload_id: TransferID,
+        offset: u64,
+    },
+    RemoveUploadInfo {
+        upload_id: TransferID,
+    },
+    /// Removes all the idle uploads of the client which is being logged-off
+    TakeClientUploads {
+        owner: FtpClientID,
+        callback: Callback<Vec<UploadInfo>>,
+    },
+    /// Temporary files which could not be removed, they are removed on the next login of the client
+    AddLeftovers {
+        owner: FtpClientID,
+        temp_paths: Vec<String>,
+    },
+    TakeLeftovers {
+        owner: FtpClientID,
+        callback: Callback<Vec<String>>,
+    },
+    Clean {
+        now: i64,
+    },
 }
@@ -0,0 +1,71 @@
+use crate::CONFIG;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Result of looking up the file in the disk cache
+#[derive(Clone, Debug)]
+pub enum DiskCacheLookup {
+    /// the current version of the file is stored locally
+    Hit(PathBuf),
+    /// the file is not cached and the caller is responsible for filling the cache
+    Fill,
+    /// the file is not cached and cannot be cached right now
+    Miss,
+}
+
+/// Checks if the file is inside one of the shared roots configured for caching
+pub fn is_cached_path(path: impl AsRef<Path>) -> bool {
+    let path = path.as_ref();
+    CONFIG
+        .disk_cache_roots
+        .iter()
+        .any(|root| path.starts_with(root))
+}
+
+/// Key of the file version, the modified file gets a new key
+/// so the outdated copy is never served
+pub fn cache_key(path: &str, size: u64, modified: i64) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{path}:{size}:{modified}").as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Path of the cached file
+pub fn data_path(key: &str) -> PathBuf {
+    Path::new(&CONFIG.disk_cache_dir).join(format!("{key}.bin"))
+}
+
+/// Path of the file which is being downloaded into the cache
+pub fn temp_path(key: &str) -> PathBuf {
+    Path::new(&CONFIG.disk_cache_dir).join(format!("{key}.part"))
+}
+
+pub fn remove_entry_file(key: &str) {
+    std::fs::remove_file(data_path(key)).ok();
+}
+
+/// Loads the keys and sizes of the files cached before the restart,
+/// the unfinished downloads are removed
+pub fn load_entries() -> Vec<(String, u64)> {
+    let dir = match std::fs::read_dir(&CONFIG.disk_cache_dir) {
+        Ok(dir) => dir,
+        Err(_) => return Vec::new(),
+    };
+
+    dir.filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("bin") => Some((
+                    path.file_stem()?.to_string_lossy().to_string(),
+                    entry.metadata().ok()?.len(),
+                )),
+                Some("part") => {
+                    std::fs::remove_file(&path).ok();
+                    None
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
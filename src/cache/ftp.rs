@@ -1,13 +1,32 @@
+mod batch;
+mod compare;
+mod diff;
+mod snapshot;
+mod stream;
+mod terminal;
 mod transfer;
+mod trash;
+mod upload;
+mod versions;
 
 use crate::cache::FtpClientID;
 use crate::handler::endpoints::login::LoginData;
 use crate::CONFIG;
 use base64::{CharacterSet, Config};
 use sha2::{Digest, Sha256};
-use ssh2::Sftp;
+use ssh2::{Session, Sftp};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub use batch::*;
+pub use compare::*;
+pub use diff::*;
+pub use snapshot::*;
+pub use stream::*;
+pub use terminal::*;
 pub use transfer::*;
+pub use trash::*;
+pub use upload::*;
+pub use versions::*;
 
 /// Hashes user credentials with SHA256 algorithm and then encodes it into base64
 pub fn hash_login(login_data: &LoginData) -> FtpClientID {
@@ -20,15 +39,63 @@ pub fn hash_login(login_data: &LoginData) -> FtpClientID {
     id
 }
 
-/// Connects to the SFTP server and returns the session
-pub fn connect(login_data: LoginData) -> anyhow::Result<Sftp> {
+/// Connects to the SSH server and returns the authenticated session
+pub fn connect_session(login_data: &LoginData) -> anyhow::Result<Session> {
     let stream = std::net::TcpStream::connect(&CONFIG.ftp_host)?;
-    let mut session = ssh2::Session::new()?;
+    let mut session = Session::new()?;
 
     session.set_tcp_stream(stream);
     session.handshake()?;
 
     session.userauth_password(&login_data.user, &login_data.password)?;
 
-    Ok(session.sftp()?)
+    Ok(session)
+}
+
+/// Connects to the SFTP server and returns the session with its connection slot
+pub fn connect(login_data: &LoginData) -> anyhow::Result<(Sftp, ConnectionSlot)> {
+    let slot = ConnectionSlot::acquire()?;
+    Ok((connect_session(login_data)?.sftp()?, slot))
+}
+
+/// Connects the additional session of the already logged-in client
+pub fn connect_additional(login_data: &LoginData) -> anyhow::Result<(Sftp, ConnectionSlot)> {
+    let slot = ConnectionSlot::acquire_additional()?;
+    Ok((connect_session(login_data)?.sftp()?, slot))
+}
+
+/// Number of the open ssh connections of all the clients
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Place for a single ssh connection reserved within the `ssh_max_connections` limit,
+/// it's released when dropped
+pub struct ConnectionSlot(());
+
+impl ConnectionSlot {
+    /// Fails if the limit of the connections has been reached
+    pub fn acquire() -> anyhow::Result<Self> {
+        Self::acquire_within(CONFIG.ssh_max_connections)
+    }
+
+    /// Slot for the additional session of the client,
+    /// a quarter of the connections is left for the logins and the terminals
+    pub fn acquire_additional() -> anyhow::Result<Self> {
+        let max = CONFIG.ssh_max_connections;
+        Self::acquire_within(max - max / 4)
+    }
+
+    fn acquire_within(max: usize) -> anyhow::Result<Self> {
+        CONNECTIONS
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                (max == 0 || connections < max).then(|| connections + 1)
+            })
+            .map(|_| Self(()))
+            .map_err(|_| anyhow::Error::msg("too many ssh connections"))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    }
 }
@@ -0,0 +1,246 @@
+use super::{move_to_trash, preserve_version, revert_preserved_version};
+use crate::CONFIG;
+use rand::RngCore;
+use ssh2::{FileType, RenameFlags, Sftp};
+use std::path::{Path, PathBuf};
+
+/// Single sftp operation requested in the batch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BatchOperation {
+    Mkdir {
+        path: String,
+    },
+    Move {
+        from: String,
+        to: String,
+        #[serde(default)]
+        overwrite: bool,
+    },
+    Delete {
+        path: String,
+        #[serde(default)]
+        recursive: bool,
+
+        /// skip the trash even if it is enabled
+        #[serde(default)]
+        permanent: bool,
+    },
+}
+
+/// Outcome of the single operation
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Ok,
+    Failed,
+
+    /// not executed because one of the previous operations has failed
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchResult {
+    pub index: usize,
+    pub status: BatchStatus,
+    pub error: Option<String>,
+}
+
+/// Results of all the operations in the same order as requested
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BatchReport {
+    pub failed: usize,
+    pub results: Vec<BatchResult>,
+}
+
+/// Removes the directory with all of its content
+fn remove_dir_recursive(stream: &Sftp, path: &Path) -> anyhow::Result<()> {
+    for (entry, stat) in stream.readdir(path)? {
+        if stat.file_type() == FileType::Directory {
+            remove_dir_recursive(stream, &entry)?;
+        } else {
+            stream.unlink(&entry)?;
+        }
+    }
+
+    stream.rmdir(path)?;
+    Ok(())
+}
+
+/// Permanently removes the file or the directory
+///
+/// * `recursive` - removes the directory even if it is not empty
+pub fn remove_path(stream: &Sftp, path: &Path, recursive: bool) -> anyhow::Result<()> {
+    if stream.lstat(path)?.file_type() == FileType::Directory {
+        if recursive {
+            remove_dir_recursive(stream, path)?;
+        } else {
+            stream.rmdir(path)?;
+        }
+    } else {
+        stream.unlink(path)?;
+    }
+
+    Ok(())
+}
+
+/// Hidden name the overwritten file is moved to until the replacing file is in place
+fn backup_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    path.with_file_name(format!(".{name}.{token}.old"))
+}
+
+/// Moves the file to the destination, replacing the existing file.
+///
+/// The overwritten file is preserved as a previous version if versioning is enabled.
+/// Otherwise the rename with the overwrite flag is tried first and if the server doesn't support it,
+/// the overwritten file is moved aside. If the final rename fails,
+/// the overwritten file is put back in place in both cases, so the destination is never lost.
+pub fn replace_file(stream: &Sftp, from: &Path, to: &Path) -> anyhow::Result<()> {
+    if from == to {
+        return Err(anyhow::Error::msg("source and destination are the same"));
+    }
+
+    match stream.lstat(to) {
+        Ok(stat) if stat.file_type() == FileType::Directory => {
+            return Err(anyhow::Error::msg("cannot overwrite a directory"));
+        }
+        Ok(_) => {}
+        Err(_) => {
+            stream.rename(from, to, None)?;
+            return Ok(());
+        }
+    }
+
+    if let Some(version) = preserve_version(stream, to)? {
+        if let Err(error) = stream.rename(from, to, None) {
+            revert_preserved_version(stream, to, &version).ok();
+            return Err(error.into());
+        }
+        return Ok(());
+    }
+
+    let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+    if stream.rename(from, to, Some(flags)).is_ok() {
+        return Ok(());
+    }
+
+    // not every sftp server supports overwriting on rename
+    let backup = backup_path(to);
+    stream.rename(to, &backup, None)?;
+    match stream.rename(from, to, None) {
+        Ok(_) => {
+            stream.unlink(&backup).ok();
+            Ok(())
+        }
+        Err(error) => {
+            stream.rename(&backup, to, None).ok();
+            Err(error.into())
+        }
+    }
+}
+
+impl BatchOperation {
+    /// Executes the operation on the sftp session
+    pub fn execute(&self, stream: &Sftp) -> anyhow::Result<()> {
+        match self {
+            BatchOperation::Mkdir { path } => {
+                stream.mkdir(Path::new(path), 0o755)?;
+            }
+
+            BatchOperation::Move {
+                from,
+                to,
+                overwrite,
+            } => {
+                let (from, to) = (Path::new(from), Path::new(to));
+                if from == to {
+                    return Err(anyhow::Error::msg("source and destination are the same"));
+                }
+
+                if stream.lstat(to).is_ok() {
+                    if !overwrite {
+                        return Err(anyhow::Error::msg("destination already exists"));
+                    }
+                    replace_file(stream, from, to)?;
+                } else {
+                    stream.rename(from, to, None)?;
+                }
+            }
+
+            BatchOperation::Delete {
+                path,
+                recursive,
+                permanent,
+            } => {
+                let path = Path::new(path);
+
+                if CONFIG.trash_enabled && !permanent {
+                    let stat = stream.lstat(path)?;
+                    if stat.file_type() == FileType::Directory
+                        && !recursive
+                        && !stream.readdir(path)?.is_empty()
+                    {
+                        return Err(anyhow::Error::msg("directory is not empty"));
+                    }
+
+                    move_to_trash(stream, path)?;
+                } else {
+                    remove_path(stream, path, *recursive)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Executes all the operations in order on the sftp session
+///
+/// * `stop_on_error` - operations after the first failure are skipped
+pub fn execute_batch(
+    stream: &Sftp,
+    operations: Vec<BatchOperation>,
+    stop_on_error: bool,
+) -> BatchReport {
+    let mut report = BatchReport {
+        failed: 0,
+        results: Vec::with_capacity(operations.len()),
+    };
+
+    for (index, operation) in operations.iter().enumerate() {
+        if stop_on_error && report.failed > 0 {
+            report.results.push(BatchResult {
+                index,
+                status: BatchStatus::Skipped,
+                error: None,
+            });
+            continue;
+        }
+
+        let result = match operation.execute(stream) {
+            Ok(_) => BatchResult {
+                index,
+                status: BatchStatus::Ok,
+                error: None,
+            },
+            Err(error) => {
+                report.failed += 1;
+                BatchResult {
+                    index,
+                    status: BatchStatus::Failed,
+                    error: Some(error.to_string()),
+                }
+            }
+        };
+        report.results.push(result);
+    }
+
+    report
+}
@@ -0,0 +1,202 @@
+use crate::CONFIG;
+use sha2::{Digest, Sha256};
+use ssh2::{FileStat, FileType, Sftp};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Type of the sftp file entity
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    Dir,
+    File,
+    Other,
+}
+
+/// Result of comparing the entity from both directories
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareStatus {
+    OnlyLeft,
+    OnlyRight,
+    Same,
+    Different,
+}
+
+/// Metadata of the entity on one of the sides
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EntryInfo {
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    pub mtime: Option<u64>,
+
+    /// SHA256 of the content, only present if hashing was requested
+    /// and the file is not bigger than `compare_max_hash_size`
+    pub hash: Option<String>,
+}
+
+impl From<&FileStat> for EntryInfo {
+    fn from(stat: &FileStat) -> Self {
+        Self {
+            kind: match stat.file_type() {
+                FileType::Directory => EntryKind::Dir,
+                FileType::RegularFile => EntryKind::File,
+                _ => EntryKind::Other,
+            },
+            size: stat.size,
+            mtime: stat.mtime,
+            hash: None,
+        }
+    }
+}
+
+/// Single compared entity, the path is relative to both compared directories
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CompareEntry {
+    pub path: String,
+    pub status: CompareStatus,
+    pub left: Option<EntryInfo>,
+    pub right: Option<EntryInfo>,
+}
+
+/// Result of comparing two directories.
+///
+/// Directories existing only on one side are not walked into
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DirComparison {
+    pub left: String,
+    pub right: String,
+    pub entries: Vec<CompareEntry>,
+}
+
+/// Computes SHA256 of the file content
+fn hash_file(stream: &Sftp, path: &Path) -> anyhow::Result<String> {
+    let mut file = stream.open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 32];
+
+    loop {
+        let read_size = file.read(&mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read_size]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Lists the directory as a map of the entity name and its metadata
+fn read_dir_map(stream: &Sftp, dir: &Path) -> anyhow::Result<BTreeMap<String, FileStat>> {
+    Ok(stream
+        .readdir(dir)?
+        .into_iter()
+        .filter_map(|(path, stat)| {
+            path.file_name()
+                .map(|name| (name.to_string_lossy().to_string(), stat))
+        })
+        .collect())
+}
+
+/// Walks both directory trees and classifies every entity.
+///
+/// Files are the same if they have equal size and modification time,
+/// or equal content hash if `hash` is set.
+pub fn compare_dirs(
+    stream: &Sftp,
+    left: String,
+    right: String,
+    hash: bool,
+) -> anyhow::Result<DirComparison> {
+    let mut entries = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        let left_dir = Path::new(&left).join(&relative);
+        let right_dir = Path::new(&right).join(&relative);
+
+        let mut left_map = read_dir_map(stream, &left_dir)?;
+        let mut right_map = read_dir_map(stream, &right_dir)?;
+
+        let mut names: Vec<String> = left_map.keys().chain(right_map.keys()).cloned().collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            if entries.len() >= CONFIG.compare_max_entries {
+                return Err(anyhow::Error::msg(format!(
+                    "comparison exceeds the limit of {} entries",
+                    CONFIG.compare_max_entries
+                )));
+            }
+
+            let path = relative.join(&name);
+            let (status, left_info, right_info) =
+                match (left_map.remove(&name), right_map.remove(&name)) {
+                    (Some(stat), None) => {
+                        (CompareStatus::OnlyLeft, Some(EntryInfo::from(&stat)), None)
+                    }
+                    (None, Some(stat)) => {
+                        (CompareStatus::OnlyRight, None, Some(EntryInfo::from(&stat)))
+                    }
+                    (Some(left_stat), Some(right_stat)) => {
+                        let mut left_info = EntryInfo::from(&left_stat);
+                        let mut right_info = EntryInfo::from(&right_stat);
+
+                        let status = match (left_info.kind, right_info.kind) {
+                            (EntryKind::Dir, EntryKind::Dir) => {
+                                pending.push(path);
+                                continue;
+                            }
+                            (EntryKind::File, EntryKind::File) => {
+                                // big files would hold the session for too long
+                                let hashable = left_info
+                                    .size
+                                    .map(|size| size <= CONFIG.compare_max_hash_size)
+                                    .unwrap_or(false);
+
+                                if left_info.size != right_info.size {
+                                    CompareStatus::Different
+                                } else if hash && hashable {
+                                    left_info.hash =
+                                        Some(hash_file(stream, &left_dir.join(&name))?);
+                                    right_info.hash =
+                                        Some(hash_file(stream, &right_dir.join(&name))?);
+
+                                    if left_info.hash == right_info.hash {
+                                        CompareStatus::Same
+                                    } else {
+                                        CompareStatus::Different
+                                    }
+                                } else if left_info.mtime == right_info.mtime {
+                                    CompareStatus::Same
+                                } else {
+                                    CompareStatus::Different
+                                }
+                            }
+                            _ => CompareStatus::Different,
+                        };
+
+                        (status, Some(left_info), Some(right_info))
+                    }
+                    (None, None) => continue,
+                };
+
+            entries.push(CompareEntry {
+                path: path.to_string_lossy().to_string(),
+                status,
+                left: left_info,
+                right: right_info,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(DirComparison {
+        left,
+        right,
+        entries,
+    })
+}
@@ -0,0 +1,191 @@
+use crate::CONFIG;
+use similar::{Algorithm, DiffTag};
+use ssh2::Sftp;
+use std::fmt::Write as _;
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+/// Kind of the line in the diff hunk
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffLineTag {
+    Equal,
+    Delete,
+    Insert,
+}
+
+impl DiffLineTag {
+    fn prefix(&self) -> char {
+        match self {
+            DiffLineTag::Equal => ' ',
+            DiffLineTag::Delete => '-',
+            DiffLineTag::Insert => '+',
+        }
+    }
+}
+
+/// Single line of the diff hunk
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiffLine {
+    pub tag: DiffLineTag,
+
+    /// line without the "\n", the "\r" of the CRLF line ending is kept
+    pub content: String,
+
+    /// false for the last line of the file which doesn't end with the new line
+    pub newline: bool,
+}
+
+/// Group of changes with the surrounding context,
+/// line numbers start from 1 like in the unified format
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// Result of comparing two text files
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileDiff {
+    pub left: String,
+    pub right: String,
+    pub identical: bool,
+
+    /// diff in the unified format
+    pub unified: String,
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// Reads the whole text file, fails if the file is bigger than `max_size`
+fn read_text_file(stream: &Sftp, path: &str, max_size: u64) -> anyhow::Result<String> {
+    let mut file = stream.open(Path::new(path))?;
+
+    let size = file.stat()?.size.unwrap_or(0);
+    if size > max_size {
+        return Err(anyhow::Error::msg(format!(
+            "file {path} exceeds the size limit of {max_size} bytes"
+        )));
+    }
+
+    let mut buffer = Vec::with_capacity(size as usize);
+    file.by_ref().take(max_size + 1).read_to_end(&mut buffer)?;
+    if buffer.len() as u64 > max_size {
+        return Err(anyhow::Error::msg(format!(
+            "file {path} exceeds the size limit of {max_size} bytes"
+        )));
+    }
+
+    String::from_utf8(buffer).map_err(|_| anyhow::Error::msg(format!("{path} is not a text file")))
+}
+
+/// Converts 0-based range into the unified hunk header position
+fn hunk_position(range: &Range<usize>) -> usize {
+    if range.is_empty() {
+        range.start
+    } else {
+        range.start + 1
+    }
+}
+
+/// Computes the line diff between two files on the sftp server
+///
+/// * `ignore_whitespace` - lines are compared with all the whitespace removed
+/// * `context` - number of unchanged lines around every change
+pub fn diff_files(
+    stream: &Sftp,
+    left: String,
+    right: String,
+    ignore_whitespace: bool,
+    context: usize,
+) -> anyhow::Result<FileDiff> {
+    let old = read_text_file(stream, &left, CONFIG.diff_max_file_size)?;
+    let new = read_text_file(stream, &right, CONFIG.diff_max_file_size)?;
+
+    // line endings are a part of the lines, so CRLF and the missing final new line are changes too
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    let key = |line: &&str| -> String {
+        if ignore_whitespace {
+            line.chars().filter(|c| !c.is_whitespace()).collect()
+        } else {
+            line.to_string()
+        }
+    };
+    let old_keys: Vec<String> = old_lines.iter().map(key).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(key).collect();
+
+    let ops = similar::capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+
+    let mut hunks = Vec::new();
+    for group in similar::group_diff_ops(ops, context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+
+        let mut lines = Vec::new();
+        let mut push = |tag: DiffLineTag, content: &[&str]| {
+            lines.extend(content.iter().map(|line| DiffLine {
+                tag,
+                content: line.strip_suffix('\n').unwrap_or(line).to_string(),
+                newline: line.ends_with('\n'),
+            }));
+        };
+
+        for op in &group {
+            let (tag, old_op, new_op) = op.as_tag_tuple();
+            match tag {
+                DiffTag::Equal => push(DiffLineTag::Equal, &old_lines[old_op]),
+                DiffTag::Delete => push(DiffLineTag::Delete, &old_lines[old_op]),
+                DiffTag::Insert => push(DiffLineTag::Insert, &new_lines[new_op]),
+                DiffTag::Replace => {
+                    push(DiffLineTag::Delete, &old_lines[old_op]);
+                    push(DiffLineTag::Insert, &new_lines[new_op]);
+                }
+            }
+        }
+
+        hunks.push(DiffHunk {
+            old_start: hunk_position(&old_range),
+            old_lines: old_range.len(),
+            new_start: hunk_position(&new_range),
+            new_lines: new_range.len(),
+            lines,
+        });
+    }
+
+    let mut unified = String::new();
+    if !hunks.is_empty() {
+        let _ = writeln!(unified, "--- {left}");
+        let _ = writeln!(unified, "+++ {right}");
+    }
+    for hunk in &hunks {
+        let _ = writeln!(
+            unified,
+            "@@ -{},{} +{},{} @@",
+            hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+        );
+        for line in &hunk.lines {
+            let _ = writeln!(unified, "{}{}", line.tag.prefix(), line.content);
+            if !line.newline {
+                let _ = writeln!(unified, "\\ No newline at end of file");
+            }
+        }
+    }
+
+    Ok(FileDiff {
+        identical: hunks.is_empty(),
+        left,
+        right,
+        unified,
+        hunks,
+    })
+}
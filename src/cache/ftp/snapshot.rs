@@ -0,0 +1,48 @@
+use crate::cache::worker::{Priority, SessionFile, SessionWorker};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Copies the file from the sftp server into the local file system
+///
+/// Fails if the file is bigger than `max_size`, the partial copy is removed on failure.
+/// Returns size of the copied file.
+pub fn snapshot_file(
+    worker: &SessionWorker,
+    file: &Path,
+    destination: &Path,
+    max_size: u64,
+) -> anyhow::Result<u64> {
+    let copy = || -> anyhow::Result<u64> {
+        let mut file = SessionFile::open_blocking(worker, file, Priority::Bulk)?;
+        if file.stat_blocking()?.size.unwrap_or(0) > max_size {
+            return Err(anyhow::Error::msg("file exceeds the size limit"));
+        }
+
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut local = std::fs::File::create(destination)?;
+
+        let mut buffer = vec![0u8; 1024 * 32];
+        let mut size = 0;
+        loop {
+            let read_size = file.read(&mut buffer)?;
+            if read_size == 0 {
+                break;
+            }
+
+            size += read_size as u64;
+            if size > max_size {
+                return Err(anyhow::Error::msg("file exceeds the size limit"));
+            }
+            local.write_all(&buffer[..read_size])?;
+        }
+
+        local.flush()?;
+        Ok(size)
+    };
+
+    copy().inspect_err(|_| {
+        std::fs::remove_file(destination).ok();
+    })
+}
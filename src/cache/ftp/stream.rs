@@ -0,0 +1,627 @@
+use crate::cache::directives::Callback;
+use crate::cache::worker::{Priority, SessionFile, SessionPool};
+use crate::cache::{Cache, FtpClientID};
+use crate::utils::throttle::Limiter;
+use crate::utils::time;
+use crate::CONFIG;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::mpsc;
+
+/// Size of a single chunk sent through the stream
+const STREAM_CHUNK_SIZE: usize = 1024 * 64;
+
+/// How many chunks can be read ahead of the client
+const STREAM_BUFFER: usize = 8;
+
+/// Size of the block read at once by a single segment of the segmented stream
+const SEGMENT_BLOCK_SIZE: u64 = 1024 * 1024 * 2;
+
+/// Byte range requested with the `Range` header
+#[derive(Copy, Clone, Debug)]
+pub enum RangeSpec {
+    /// `bytes=<start>-[<end>]`
+    FromTo(u64, Option<u64>),
+    /// `bytes=-<length>`
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    /// Parses the `Range` header value.
+    ///
+    /// Returns None for the invalid or multipart ranges, so they are ignored.
+    pub fn parse(header: &str) -> Option<Self> {
+        let range = header.trim().strip_prefix("bytes=")?;
+        if range.contains(',') {
+            return None;
+        }
+
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        if start.is_empty() {
+            return end.parse().ok().map(Self::Suffix);
+        }
+
+        let start = start.parse().ok()?;
+        let end = if end.is_empty() {
+            None
+        } else {
+            Some(end.parse().ok()?)
+        };
+
+        match end {
+            Some(end) if end < start => None,
+            _ => Some(Self::FromTo(start, end)),
+        }
+    }
+
+    /// Resolves the range against the size of the file,
+    /// returns None if the range is not satisfiable
+    pub fn resolve(&self, size: u64) -> Option<ByteRange> {
+        if size == 0 {
+            return None;
+        }
+
+        match *self {
+            Self::FromTo(start, _) if start >= size => None,
+            Self::FromTo(start, end) => Some(ByteRange {
+                start,
+                end: end.unwrap_or(size - 1).min(size - 1),
+            }),
+            Self::Suffix(0) => None,
+            Self::Suffix(length) => Some(ByteRange {
+                start: size.saturating_sub(length),
+                end: size - 1,
+            }),
+        }
+    }
+}
+
+/// Resolved range of bytes, both ends are inclusive
+#[derive(Copy, Clone, Debug)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Part of the file which is streamed to the client
+#[derive(Copy, Clone, Debug)]
+pub enum StreamRange {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+/// Conditional headers of the request which allow the client to reuse its cached copy
+#[derive(Clone, Debug, Default)]
+pub struct Conditions {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<String>,
+}
+
+impl Conditions {
+    /// True if the request has no conditional headers
+    pub fn is_empty(&self) -> bool {
+        self.if_none_match.is_none() && self.if_modified_since.is_none()
+    }
+
+    /// Checks if the cached copy of the client is still up to date.
+    ///
+    /// `If-Modified-Since` is ignored when `If-None-Match` is present (RFC 7232).
+    pub fn not_modified(&self, etag: &str, modified: Option<i64>) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return etag_matches(if_none_match, etag);
+        }
+
+        match (&self.if_modified_since, modified) {
+            (Some(since), Some(modified)) => time::parse_http_date(since)
+                .map(|since| modified <= since)
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+}
+
+/// Weak comparison of the `If-None-Match` header value with the ETag.
+///
+/// Tags of the compressed representations (ETag with the encoding suffix) match as well.
+pub fn etag_matches(header: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| {
+        tag.trim()
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .to_string()
+    };
+    let etag = opaque(etag);
+
+    header.split(',').map(opaque).any(|tag| {
+        tag == "*"
+            || tag == etag
+            || tag
+                .strip_prefix(&etag)
+                .map(|suffix| suffix.starts_with('-'))
+                .unwrap_or(false)
+    })
+}
+
+/// Short hex digest of the content used in the ETags
+pub fn etag_digest(data: &[u8]) -> String {
+    Sha256::digest(data)[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Strong validator of the file built from its path, size and modification time
+pub fn file_etag(path: &str, size: u64, modified: i64) -> String {
+    format!(
+        "\"{}-{:x}-{:x}\"",
+        etag_digest(path.as_bytes()),
+        size,
+        modified
+    )
+}
+
+/// Opened file stream with the metadata required for the response headers
+pub struct FileStream {
+    pub path: String,
+    pub size: u64,
+    pub modified: i64,
+    pub range: StreamRange,
+
+    /// set if the client's copy is up to date, nothing is streamed in such case
+    pub not_modified: bool,
+    pub chunks: mpsc::Receiver<anyhow::Result<Vec<u8>>>,
+}
+
+impl FileStream {
+    pub fn etag(&self) -> String {
+        file_etag(&self.path, self.size, self.modified)
+    }
+
+    pub fn last_modified(&self) -> String {
+        time::http_date(self.modified)
+    }
+
+    /// Checks the `If-Range` header value against the ETag or the Last-Modified date
+    pub fn matches(&self, validator: &str) -> bool {
+        let validator = validator.trim();
+        validator == self.etag() || validator == self.last_modified()
+    }
+
+    /// Number of bytes which are going to be streamed
+    pub fn content_length(&self) -> u64 {
+        match self.range {
+            StreamRange::Full => self.size,
+            StreamRange::Partial(range) => range.size(),
+            StreamRange::Unsatisfiable => 0,
+        }
+    }
+}
+
+/// Part of the file and the validators requested by the client
+#[derive(Clone, Debug, Default)]
+pub struct StreamRequest {
+    pub range: Option<RangeSpec>,
+    pub if_range: Option<String>,
+    pub conditions: Conditions,
+}
+
+/// Opens the file and streams its content (or the requested range) through the channel.
+///
+/// The metadata is returned in the callback before the streaming starts,
+/// the function returns once the range has been read or the receiver was dropped.
+/// Every read is queued on the session worker, so the stream doesn't block the other commands
+/// and no thread waits for the client while it's reading the stream.
+/// Large ranges are read in the parallel segments through the sessions of the pool.
+pub async fn stream_file(
+    pool: SessionPool,
+    id: FtpClientID,
+    file: &Path,
+    request: StreamRequest,
+    limiter: Limiter,
+    callback: Callback<anyhow::Result<FileStream>>,
+) {
+    let path = file.to_string_lossy().to_string();
+    let open = async {
+        let file = SessionFile::open(&pool.pick(), file, Priority::Bulk).await?;
+        let stat = file.stat().await?;
+        if stat.is_dir() {
+            return Err(anyhow::Error::msg("cannot stream a directory"));
+        }
+        Ok((file, stat.size.unwrap_or(0), stat.mtime.unwrap_or(0) as i64))
+    };
+
+    match open.await {
+        Ok((file, size, modified)) => {
+            let source = StreamSource::Remote { file, pool, id };
+            stream_content(source, path, size, modified, request, limiter, callback).await
+        }
+        Err(error) => {
+            callback.send(Err(error)).ok();
+        }
+    }
+}
+
+/// Streams the local copy of the remote file,
+/// the validators are built from the metadata of the remote file
+pub async fn stream_local_file(
+    local: &Path,
+    path: String,
+    size: u64,
+    modified: i64,
+    request: StreamRequest,
+    limiter: Limiter,
+    callback: Callback<anyhow::Result<FileStream>>,
+) {
+    match tokio::fs::File::open(local).await {
+        Ok(file) => {
+            stream_content(
+                StreamSource::Local(file),
+                path,
+                size,
+                modified,
+                request,
+                limiter,
+                callback,
+            )
+            .await
+        }
+        Err(error) => {
+            callback.send(Err(error.into())).ok();
+        }
+    }
+}
+
+/// Where the streamed content is read from
+enum StreamSource {
+    Local(tokio::fs::File),
+    /// the pool is used to read the large ranges in the parallel segments
+    Remote {
+        file: SessionFile,
+        pool: SessionPool,
+        id: FtpClientID,
+    },
+}
+
+impl StreamSource {
+    async fn seek_to(&mut self, offset: u64) -> std::io::Result<()> {
+        match self {
+            Self::Local(file) => file.seek(SeekFrom::Start(offset)).await.map(|_| ()),
+            Self::Remote { file, .. } => file.seek_to(offset).await,
+        }
+    }
+
+    /// Reads at most `size` bytes, the empty chunk marks the end of the file
+    async fn read_chunk(&mut self, size: usize) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Local(file) => {
+                let mut buffer = vec![0u8; size];
+                let read_size = file.read(&mut buffer).await?;
+                buffer.truncate(read_size);
+                Ok(buffer)
+            }
+            Self::Remote { file, .. } => file.read_chunk(size).await,
+        }
+    }
+}
+
+/// If `if_range` does not match the current file, the whole file is streamed.
+/// If the `conditions` are met, the file is not read at all.
+async fn stream_content(
+    source: StreamSource,
+    path: String,
+    size: u64,
+    modified: i64,
+    request: StreamRequest,
+    limiter: Limiter,
+    callback: Callback<anyhow::Result<FileStream>>,
+) {
+    let StreamRequest {
+        range,
+        if_range,
+        conditions,
+    } = request;
+
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    let mut file_stream = FileStream {
+        path,
+        size,
+        modified,
+        range: StreamRange::Full,
+        not_modified: false,
+        chunks: rx,
+    };
+
+    if conditions.not_modified(&file_stream.etag(), Some(modified)) {
+        file_stream.not_modified = true;
+        callback.send(Ok(file_stream)).ok();
+        return;
+    }
+
+    let if_range_matches = if_range
+        .map(|validator| file_stream.matches(&validator))
+        .unwrap_or(true);
+    if let Some(range) = range.filter(|_| if_range_matches) {
+        file_stream.range = match range.resolve(size) {
+            Some(range) => StreamRange::Partial(range),
+            None => StreamRange::Unsatisfiable,
+        };
+    }
+
+    let (start, left) = match file_stream.range {
+        StreamRange::Full => (0, size),
+        StreamRange::Partial(range) => (range.start, range.size()),
+        StreamRange::Unsatisfiable => (0, 0),
+    };
+
+    // a single session would read the segments one after another anyway
+    let segmented =
+        CONFIG.segmented_download_segments > 1 && left >= CONFIG.segmented_download_min_size;
+    let source = match source {
+        StreamSource::Remote { file, pool, .. } if segmented && pool.sessions() > 1 => {
+            let path = file_stream.path.clone();
+            match open_segments(file, &pool, Path::new(&path)).await {
+                Ok(files) => {
+                    if callback.send(Ok(file_stream)).is_ok() {
+                        read_segmented(files, start, left, tx, limiter).await;
+                    }
+                }
+                Err(error) => {
+                    callback.send(Err(error)).ok();
+                }
+            }
+            return;
+        }
+        // the next large streams can be segmented once the session is opened
+        StreamSource::Remote { file, pool, id } if segmented => {
+            Cache::ftp_request_session(id.clone()).await;
+            StreamSource::Remote { file, pool, id }
+        }
+        source => source,
+    };
+
+    read_sequential(source, start, left, file_stream, tx, limiter, callback).await
+}
+
+/// Reads the range through the single handle of the file
+async fn read_sequential(
+    mut source: StreamSource,
+    start: u64,
+    mut left: u64,
+    file_stream: FileStream,
+    tx: mpsc::Sender<anyhow::Result<Vec<u8>>>,
+    limiter: Limiter,
+    callback: Callback<anyhow::Result<FileStream>>,
+) {
+    if start > 0 {
+        if let Err(error) = source.seek_to(start).await {
+            callback.send(Err(error.into())).ok();
+            return;
+        }
+    }
+
+    if callback.send(Ok(file_stream)).is_err() {
+        return;
+    }
+
+    while left > 0 {
+        let to_read = left.min(STREAM_CHUNK_SIZE as u64) as usize;
+        let chunk = match source.read_chunk(to_read).await {
+            Ok(chunk) if chunk.is_empty() => Err(anyhow::Error::msg("unexpected end of the file")),
+            Ok(chunk) => {
+                left -= chunk.len() as u64;
+                limiter.consume(chunk.len() as u64).await;
+                Ok(chunk)
+            }
+            Err(error) => Err(error.into()),
+        };
+
+        let failed = chunk.is_err();
+        if tx.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Opens the handles of the segments, each on the least busy session at the time,
+/// so the segments are spread across the sessions of the pool.
+///
+/// The already opened file is used by the first segment.
+async fn open_segments(
+    file: SessionFile,
+    pool: &SessionPool,
+    path: &Path,
+) -> anyhow::Result<Vec<SessionFile>> {
+    let mut files = vec![file];
+    for _ in 1..CONFIG.segmented_download_segments {
+        files.push(SessionFile::open(&pool.pick(), path, Priority::Bulk).await?);
+    }
+    Ok(files)
+}
+
+/// Reads the range in blocks through multiple handles of the file, on different sessions,
+/// as the window of a single sftp channel limits the throughput.
+///
+/// Every segment reads every n-th block through its own handle, the blocks are sent to the client in order.
+/// The reads are queued on the session workers, a single block of every segment is read at once.
+async fn read_segmented(
+    files: Vec<SessionFile>,
+    start: u64,
+    left: u64,
+    tx: mpsc::Sender<anyhow::Result<Vec<u8>>>,
+    limiter: Limiter,
+) {
+    let segments = files.len() as u64;
+    let blocks = left.div_ceil(SEGMENT_BLOCK_SIZE);
+
+    let read_block = |block: u64| {
+        let offset = block * SEGMENT_BLOCK_SIZE;
+        let size = SEGMENT_BLOCK_SIZE.min(left - offset) as usize;
+        files[(block % segments) as usize].read_block(start + offset, size)
+    };
+
+    let mut pending: VecDeque<_> = (0..segments.min(blocks)).map(read_block).collect();
+    for block in 0..blocks {
+        let bytes = match pending.pop_front() {
+            Some(pending_block) => match pending_block.await {
+                Ok(bytes) => bytes.map_err(anyhow::Error::from),
+                Err(_) => Err(anyhow::Error::msg("sftp session has been closed")),
+            },
+            None => Err(anyhow::Error::msg("sftp session has been closed")),
+        };
+
+        // the segment reads its next block while this one is being sent
+        if block + segments < blocks {
+            pending.push_back(read_block(block + segments));
+        }
+
+        match bytes {
+            Ok(bytes) => {
+                for chunk in bytes.chunks(STREAM_CHUNK_SIZE) {
+                    limiter.consume(chunk.len() as u64).await;
+                    if tx.send(Ok(chunk.to_vec())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(error) => {
+                tx.send(Err(error)).await.ok();
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // `#[macro_use]` of tokio shadows the built-in test attribute
+    use std::prelude::v1::test;
+
+    fn resolve(header: &str, size: u64) -> Option<(u64, u64)> {
+        RangeSpec::parse(header)?
+            .resolve(size)
+            .map(|range| (range.start, range.end))
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert!(matches!(
+            RangeSpec::parse("bytes=0-99"),
+            Some(RangeSpec::FromTo(0, Some(99)))
+        ));
+        assert!(matches!(
+            RangeSpec::parse(" bytes=100-"),
+            Some(RangeSpec::FromTo(100, None))
+        ));
+        assert!(matches!(
+            RangeSpec::parse("bytes=-500"),
+            Some(RangeSpec::Suffix(500))
+        ));
+        assert!(matches!(
+            RangeSpec::parse("bytes=-0"),
+            Some(RangeSpec::Suffix(0))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(RangeSpec::parse("bytes=10-5").is_none());
+        assert!(RangeSpec::parse("bytes=0-9,20-29").is_none());
+        assert!(RangeSpec::parse("items=0-9").is_none());
+        assert!(RangeSpec::parse("bytes=a-9").is_none());
+        assert!(RangeSpec::parse("bytes=-").is_none());
+    }
+
+    #[test]
+    fn resolves_against_file_size() {
+        assert_eq!(resolve("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(resolve("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(resolve("bytes=900-5000", 1000), Some((900, 999)));
+        assert_eq!(resolve("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(resolve("bytes=999-999", 1000), Some((999, 999)));
+    }
+
+    #[test]
+    fn suffix_larger_than_file_is_whole_file() {
+        assert_eq!(resolve("bytes=-5000", 1000), Some((0, 999)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(resolve("bytes=-0", 1000), None);
+        assert_eq!(resolve("bytes=1000-", 1000), None);
+        assert_eq!(resolve("bytes=2000-3000", 1000), None);
+    }
+
+    #[test]
+    fn zero_length_file_is_never_satisfiable() {
+        assert_eq!(resolve("bytes=0-", 0), None);
+        assert_eq!(resolve("bytes=0-0", 0), None);
+        assert_eq!(resolve("bytes=-10", 0), None);
+    }
+
+    fn conditions(if_none_match: Option<&str>, if_modified_since: Option<&str>) -> Conditions {
+        Conditions {
+            if_none_match: if_none_match.map(str::to_string),
+            if_modified_since: if_modified_since.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn etag_matches_weakly() {
+        let etag = "\"abc-10-5\"";
+        assert!(etag_matches("\"abc-10-5\"", etag));
+        assert!(etag_matches("W/\"abc-10-5\"", etag));
+        assert!(etag_matches("\"other\", \"abc-10-5\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"other\"", etag));
+        assert!(!etag_matches("\"abc-10\"", etag));
+    }
+
+    #[test]
+    fn etag_of_compressed_representation_matches() {
+        let etag = "\"abc-10-5\"";
+        assert!(etag_matches("\"abc-10-5-gzip\"", etag));
+        assert!(!etag_matches("\"abc-10-50\"", etag));
+    }
+
+    #[test]
+    fn not_modified_with_if_modified_since() {
+        let since = time::http_date(1_000_000);
+        let request = conditions(None, Some(&since));
+        assert!(request.not_modified("\"etag\"", Some(1_000_000)));
+        assert!(request.not_modified("\"etag\"", Some(999_999)));
+        assert!(!request.not_modified("\"etag\"", Some(1_000_001)));
+        assert!(!request.not_modified("\"etag\"", None));
+        assert!(!conditions(None, Some("not a date")).not_modified("\"etag\"", Some(0)));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence() {
+        let since = time::http_date(1_000_000);
+        let request = conditions(Some("\"other\""), Some(&since));
+        assert!(!request.not_modified("\"etag\"", Some(0)));
+
+        let request = conditions(Some("\"etag\""), Some(&since));
+        assert!(request.not_modified("\"etag\"", Some(2_000_000)));
+    }
+
+    #[test]
+    fn no_conditions_are_never_not_modified() {
+        let request = Conditions::default();
+        assert!(request.is_empty());
+        assert!(!request.not_modified("\"etag\"", Some(0)));
+    }
+}
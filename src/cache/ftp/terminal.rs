@@ -0,0 +1,107 @@
+use super::{connect_session, ConnectionSlot};
+use crate::handler::endpoints::login::LoginData;
+use ssh2::{Channel, ErrorCode};
+use std::io::{ErrorKind, Read, Write};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
+
+/// How long the terminal waits when there is nothing to relay
+const TERMINAL_POLL_DELAY: Duration = Duration::from_millis(10);
+
+/// libssh2 error returned by the non-blocking session when the operation would block
+const LIBSSH2_ERROR_EAGAIN: i32 = -37;
+
+/// Data sent from the client into the terminal
+pub enum TerminalInput {
+    Data(Vec<u8>),
+    Resize { cols: u32, rows: u32 },
+}
+
+/// Repeats the ssh operation until the non-blocking session is able to complete it
+fn retry_eagain<T>(mut operation: impl FnMut() -> Result<T, ssh2::Error>) -> anyhow::Result<T> {
+    loop {
+        match operation() {
+            Err(error) if error.code() == ErrorCode::Session(LIBSSH2_ERROR_EAGAIN) => {
+                std::thread::sleep(TERMINAL_POLL_DELAY);
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Writes all the bytes into the channel of the non-blocking session
+fn write_all(channel: &mut Channel, mut data: &[u8]) -> anyhow::Result<()> {
+    while !data.is_empty() {
+        match channel.write(data) {
+            Ok(written) => data = &data[written..],
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                std::thread::sleep(TERMINAL_POLL_DELAY);
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens a new ssh session with the PTY shell and relays the terminal bytes
+/// until the shell exits or the input channel gets closed.
+///
+/// A dedicated session is used, because reading the shell output
+/// would block every sftp operation on the shared session.
+pub fn run_terminal(
+    login_data: LoginData,
+    cols: u32,
+    rows: u32,
+    mut input: mpsc::Receiver<TerminalInput>,
+    output: mpsc::Sender<Vec<u8>>,
+) -> anyhow::Result<()> {
+    let _slot = ConnectionSlot::acquire()?;
+    let session = connect_session(&login_data)?;
+    let mut channel = session.channel_session()?;
+
+    channel.request_pty("xterm-256color", None, Some((cols, rows, 0, 0)))?;
+    channel.shell()?;
+    session.set_blocking(false);
+
+    let mut buffer = vec![0u8; 1024 * 16];
+    loop {
+        let mut idle = true;
+
+        loop {
+            match input.try_recv() {
+                Ok(TerminalInput::Data(data)) => {
+                    write_all(&mut channel, &data)?;
+                    idle = false;
+                }
+                Ok(TerminalInput::Resize { cols, rows }) => {
+                    retry_eagain(|| channel.request_pty_size(cols, rows, None, None))?;
+                    idle = false;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        match channel.read(&mut buffer) {
+            Ok(0) => {}
+            Ok(read_size) => {
+                if output.blocking_send(buffer[..read_size].to_vec()).is_err() {
+                    return Ok(());
+                }
+                idle = false;
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+            Err(error) => return Err(error.into()),
+        }
+
+        if channel.eof() {
+            return Ok(());
+        }
+
+        if idle {
+            std::thread::sleep(TERMINAL_POLL_DELAY);
+        }
+    }
+}
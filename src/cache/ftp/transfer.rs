@@ -1,36 +1,249 @@
 use super::*;
-use ssh2::File;
+use crate::cache::worker::{Priority, SessionFile, SessionWorker};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::io::{Error, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// The smallest chunk size used by the transfers
+pub const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Adaptive transfers aim for the single read to take about this long
+const ADAPTIVE_TARGET_READ_TIME: Duration = Duration::from_millis(250);
+
+fn max_chunk_size() -> usize {
+    CONFIG.transfer_max_chunk_size.max(MIN_CHUNK_SIZE)
+}
+
+/// Chunk size requested by the client for the new transfer
+#[derive(Copy, Clone, Debug)]
+pub enum ChunkSize {
+    Fixed(usize),
+    /// Chunk size grows and shrinks based on the measured read throughput
+    Auto,
+}
+
+impl ChunkSize {
+    /// Parses the `pack-chunk-size` header value, either a number of bytes or "auto"
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim() {
+            "auto" => Some(Self::Auto),
+            value => value.parse().ok().map(Self::Fixed),
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub enum NoCallback {}
 
+/// File read by the transfer, either from the sftp server or from the disk cache
+pub enum TransferSource {
+    Local(std::fs::File),
+    Remote(SessionFile),
+}
+
+impl TransferSource {
+    fn read_chunk_blocking(&mut self, size: usize) -> std::io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; size];
+        let read_size = match self {
+            Self::Local(file) => file.read(&mut buffer)?,
+            Self::Remote(file) => file.read(&mut buffer)?,
+        };
+        buffer.truncate(read_size);
+        Ok(buffer)
+    }
+}
+
+type Chunk = std::io::Result<(Vec<u8>, Duration)>;
+
+/// Background reader which keeps the upcoming chunks of the file in memory,
+/// so the packs are not waiting for the sftp server.
+///
+/// The first pack is read directly, the reading ahead starts once the transfer is continued.
+/// It runs as the async task which queues a single read at a time on the session worker,
+/// so it holds no thread while the client is not asking for the packs.
+/// The task owns the file handle and stops when the reader is dropped.
+pub struct PrefetchReader {
+    /// file which is not read ahead yet
+    source: Option<TransferSource>,
+    /// the first chunk has been read directly
+    first_read: bool,
+    /// read chunks with the time the read took
+    chunks: Option<mpsc::Receiver<Chunk>>,
+    chunk_size: Arc<AtomicUsize>,
+}
+
+impl PrefetchReader {
+    pub fn new(source: TransferSource, chunk_size: usize) -> Self {
+        Self {
+            source: Some(source),
+            first_read: false,
+            chunks: None,
+            chunk_size: Arc::new(AtomicUsize::new(chunk_size)),
+        }
+    }
+
+    /// Takes the next chunk, waits if it hasn't been read yet
+    pub fn next_chunk(&mut self) -> Chunk {
+        match self.source.take() {
+            // the transfers which are never continued don't read ahead
+            Some(mut source) if !self.first_read => {
+                self.first_read = true;
+                let read_start = Instant::now();
+                let chunk = source.read_chunk_blocking(self.chunk_size.load(Ordering::Relaxed));
+                self.source = Some(source);
+                return chunk.map(|bytes| (bytes, read_start.elapsed()));
+            }
+            Some(source) => {
+                let (tx, rx) = mpsc::channel(CONFIG.transfer_prefetch_chunks.max(1));
+                tokio::spawn(prefetch(source, self.chunk_size.clone(), tx));
+                self.chunks = Some(rx);
+            }
+            None => {}
+        }
+
+        self.chunks
+            .as_mut()
+            .and_then(|chunks| chunks.blocking_recv())
+            .unwrap_or_else(|| Err(Error::other("transfer reader has stopped")))
+    }
+
+    /// Changes the size of the chunks which are not read yet
+    pub fn set_chunk_size(&self, chunk_size: usize) {
+        self.chunk_size.store(chunk_size, Ordering::Relaxed);
+    }
+}
+
+enum PrefetchSource {
+    Local(tokio::fs::File),
+    Remote(SessionFile),
+}
+
+/// Reads the chunks ahead until the end of the file or until the reader is dropped
+async fn prefetch(source: TransferSource, chunk_size: Arc<AtomicUsize>, tx: mpsc::Sender<Chunk>) {
+    let mut source = match source {
+        TransferSource::Local(file) => PrefetchSource::Local(tokio::fs::File::from_std(file)),
+        TransferSource::Remote(file) => PrefetchSource::Remote(file),
+    };
+
+    loop {
+        let size = chunk_size.load(Ordering::Relaxed);
+        let read_start = Instant::now();
+        let chunk = match &mut source {
+            PrefetchSource::Local(file) => {
+                let mut buffer = vec![0u8; size];
+                file.read(&mut buffer).await.map(|read_size| {
+                    buffer.truncate(read_size);
+                    buffer
+                })
+            }
+            PrefetchSource::Remote(file) => file.read_chunk(size).await,
+        }
+        .map(|bytes| (bytes, read_start.elapsed()));
+
+        // the empty chunk marks the end of the file
+        let is_end = !matches!(&chunk, Ok((bytes, _)) if !bytes.is_empty());
+        if tx.send(chunk).await.is_err() || is_end {
+            return;
+        }
+    }
+}
+
 /// Used in transfer cache system for saving metadata about the file transfer.
 ///
-/// It also contains the file handle to save progress of the transfer
+/// It also contains the reader of the file to save progress of the transfer
 pub struct TransferInfo {
+    pub path: String,
+    pub started_timestamp: i64,
     pub last_usage_timestamp: i64,
     pub file_size: u64,
     pub total_read_size: u64,
     pub chunk_size: usize,
     pub chunks_sent: usize,
-    pub file: Option<File>,
+    pub adaptive: bool,
+    pub reader: Option<PrefetchReader>,
+
+    /// offset and size of every sent pack, so the packs can be requested again
+    pub packs: Vec<(u64, usize)>,
+
+    /// digest of the content sent so far
+    pub digest: Sha256,
+
+    /// digest of the whole file, set once the last pack has been sent
+    pub file_digest: Option<String>,
 }
 
 impl Default for TransferInfo {
     fn default() -> Self {
+        let now = chrono::Local::now().timestamp();
         Self {
-            last_usage_timestamp: chrono::Local::now().timestamp(),
+            path: String::new(),
+            started_timestamp: now,
+            last_usage_timestamp: now,
             file_size: 0,
             total_read_size: 0,
-            chunk_size: 1024,
+            chunk_size: CONFIG
+                .transfer_default_chunk_size
+                .clamp(MIN_CHUNK_SIZE, max_chunk_size()),
             chunks_sent: 0,
-            file: None,
+            adaptive: false,
+            reader: None,
+            packs: Vec::new(),
+            digest: Sha256::new(),
+            file_digest: None,
         }
     }
 }
 
 impl TransferInfo {
+    /// Applies the chunk size requested by the client, limited by the server's maximum
+    pub fn set_chunk_size(&mut self, chunk_size: ChunkSize) {
+        let max_chunk_size = max_chunk_size();
+        match chunk_size {
+            ChunkSize::Fixed(size) => {
+                self.chunk_size = size.clamp(MIN_CHUNK_SIZE, max_chunk_size);
+            }
+            ChunkSize::Auto => {
+                self.chunk_size = self.chunk_size.clamp(MIN_CHUNK_SIZE, max_chunk_size);
+                self.adaptive = true;
+            }
+        }
+    }
+
+    /// Adjusts the chunk size of the adaptive transfer after the read.
+    ///
+    /// Fast reads double the size and slow reads halve it.
+    /// The server can return less than requested in a single read,
+    /// so reads filling at least half of the chunk are treated as full.
+    pub fn adapt_chunk_size(&mut self, read_size: usize, elapsed: Duration) {
+        if !self.adaptive {
+            return;
+        }
+
+        let max_chunk_size = max_chunk_size();
+        if read_size >= self.chunk_size / 2 && elapsed < ADAPTIVE_TARGET_READ_TIME / 2 {
+            self.chunk_size = (self.chunk_size * 2).min(max_chunk_size);
+        } else if elapsed > ADAPTIVE_TARGET_READ_TIME * 2 {
+            self.chunk_size = (self.chunk_size / 2).max(MIN_CHUNK_SIZE);
+        }
+    }
+
+    /// Number of packs left after the current one, assuming the current chunk size
+    pub fn packs_left(&self) -> u64 {
+        let bytes_left = self.file_size.saturating_sub(self.total_read_size);
+        bytes_left.div_ceil(self.chunk_size as u64)
+    }
+
+    /// Checks if the pack with the number has been sent as the last one
+    pub fn is_last_pack(&self, no: u64) -> bool {
+        self.file_digest.is_some() && no + 1 == self.packs.len() as u64
+    }
+
     #[allow(dead_code)]
     #[deprecated]
     /// Used to calculate optimal mpsc channel buffer based on the file and chunk size
@@ -44,6 +257,34 @@ impl TransferInfo {
     }
 }
 
+/// State of the transfer presented to its owner
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransferStatus {
+    pub transfer: String,
+    pub path: String,
+    pub read_size: u64,
+    pub file_size: u64,
+    /// average read speed in bytes per second
+    pub rate: u64,
+    pub started_at: i64,
+    pub last_activity: i64,
+}
+
+impl TransferStatus {
+    pub fn new(transfer_id: &TransferID, info: &TransferInfo, now: i64) -> Self {
+        let elapsed = (now - info.started_timestamp).max(1) as u64;
+        Self {
+            transfer: transfer_id.token().to_string(),
+            path: info.path.clone(),
+            read_size: info.total_read_size,
+            file_size: info.file_size,
+            rate: info.total_read_size / elapsed,
+            started_at: info.started_timestamp,
+            last_activity: info.last_usage_timestamp,
+        }
+    }
+}
+
 /*pub struct TransferDirective {
     pub buffer_size: usize,
     pub callback: oneshot::Sender<anyhow::Result<Vec<u8>>>
@@ -65,19 +306,77 @@ pub struct FileContentPack {
 
     /// how many packs left to read
     pub packs_left: u64,
+
+    /// chunk size which will be used for the next pack
+    pub chunk_size: usize,
+
+    /// token of the transfer required to read the next packs
+    pub transfer: String,
+
+    /// SHA256 of the bytes in hex
+    pub checksum: String,
+
+    /// SHA256 of the whole file in hex, present only in the last pack
+    pub file_checksum: Option<String>,
+}
+
+/// Computes SHA256 of the pack content
+pub fn pack_checksum(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Reads the part of the file through a new handle.
+///
+/// Used for the packs which have already been sent, as the prefetch reader only moves forward,
+/// and for the blocks of the segmented streams.
+pub fn read_pack(
+    worker: &SessionWorker,
+    path: &Path,
+    offset: u64,
+    size: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let mut file = SessionFile::open_blocking(worker, path, Priority::Bulk)?;
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut buffer = vec![0u8; size];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
 }
 
-/// Identification of the user-file specific transfer.
-/// Never revealed to the user
+/// Identification of the user specific transfer.
+///
+/// Only the opaque token is revealed to the user,
+/// it's bound to the client id so other users cannot access the transfer
 #[derive(Clone, Hash, Debug, Eq, PartialEq)]
 pub struct TransferID {
     inner: Arc<String>,
+    token: Arc<String>,
+    owner: FtpClientID,
 }
 
 impl TransferID {
-    pub fn new(id: FtpClientID, filename: impl ToString) -> Self {
+    pub fn new(id: FtpClientID, token: impl ToString) -> Self {
+        let token = token.to_string();
         Self {
-            inner: Arc::new(format!("{id}-{}", filename.to_string())),
+            inner: Arc::new(format!("{id}-{token}")),
+            token: Arc::new(token),
+            owner: id,
         }
     }
+
+    /// Creates the id of a new transfer with the random token
+    pub fn generate(id: FtpClientID) -> Self {
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self::new(id, base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+    }
+
+    /// Token which identifies the transfer in the `pack-transfer` header
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn owner(&self) -> &FtpClientID {
+        &self.owner
+    }
 }
@@ -0,0 +1,154 @@
+use super::remove_path;
+use crate::utils::time;
+use ssh2::{FileType, Sftp};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Directory relative to the user's home directory where deleted entities are moved
+pub const TRASH_DIR: &str = ".pjatk-trash";
+
+/// Extension of the metadata file saved next to every trashed entity
+const TRASH_META_EXTENSION: &str = "meta.json";
+
+/// Metadata of the entity moved into the trash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrashEntry {
+    pub id: String,
+    pub original_path: String,
+    pub deleted_at: i64,
+    pub is_dir: bool,
+}
+
+fn entry_path(id: &str) -> PathBuf {
+    Path::new(TRASH_DIR).join(id)
+}
+
+fn meta_path(id: &str) -> PathBuf {
+    Path::new(TRASH_DIR).join(format!("{id}.{TRASH_META_EXTENSION}"))
+}
+
+/// Makes sure the id points directly into the trash directory
+fn validate_id(id: &str) -> anyhow::Result<&str> {
+    if id.is_empty() || id.contains('/') || id == "." || id == ".." {
+        Err(anyhow::Error::msg("invalid trash id"))
+    } else {
+        Ok(id)
+    }
+}
+
+fn read_meta(stream: &Sftp, path: &Path) -> anyhow::Result<TrashEntry> {
+    let mut buffer = Vec::new();
+    stream.open(path)?.read_to_end(&mut buffer)?;
+    Ok(serde_json::from_slice(&buffer)?)
+}
+
+/// Moves the entity into the user's trash directory and saves its metadata
+pub fn move_to_trash(stream: &Sftp, path: &Path) -> anyhow::Result<TrashEntry> {
+    let stat = stream.lstat(path)?;
+
+    if stream.lstat(Path::new(TRASH_DIR)).is_err() {
+        stream.mkdir(Path::new(TRASH_DIR), 0o700)?;
+    }
+
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::Error::msg("cannot move this path into the trash"))?;
+
+    let deleted_at = time::now();
+    let mut id = format!("{deleted_at}-{name}");
+    let mut suffix = 1;
+    while stream.lstat(&entry_path(&id)).is_ok() {
+        id = format!("{deleted_at}-{suffix}-{name}");
+        suffix += 1;
+    }
+
+    let entry = TrashEntry {
+        original_path: path.to_string_lossy().to_string(),
+        deleted_at,
+        is_dir: stat.file_type() == FileType::Directory,
+        id,
+    };
+
+    stream.rename(path, &entry_path(&entry.id), None)?;
+
+    let meta = serde_json::to_string(&entry).unwrap();
+    let saved = stream
+        .create(&meta_path(&entry.id))
+        .map_err(anyhow::Error::from)
+        .and_then(|mut file| Ok(file.write_all(meta.as_bytes())?));
+    if let Err(error) = saved {
+        // without metadata the entity could not be restored
+        stream.rename(&entry_path(&entry.id), path, None).ok();
+        return Err(error);
+    }
+
+    Ok(entry)
+}
+
+/// Lists all the entities in the user's trash, the newest first
+pub fn list_trash(stream: &Sftp) -> anyhow::Result<Vec<TrashEntry>> {
+    let listing = match stream.readdir(Path::new(TRASH_DIR)) {
+        Ok(listing) => listing,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut entries: Vec<TrashEntry> = listing
+        .into_iter()
+        .filter(|(path, _)| path.to_string_lossy().ends_with(TRASH_META_EXTENSION))
+        .filter_map(|(path, _)| read_meta(stream, &path).ok())
+        .collect();
+
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+    Ok(entries)
+}
+
+/// Moves the entity from the trash back to its original path, or to `to` if provided
+pub fn restore_from_trash(
+    stream: &Sftp,
+    id: &str,
+    to: Option<String>,
+) -> anyhow::Result<TrashEntry> {
+    let entry = read_meta(stream, &meta_path(validate_id(id)?))?;
+    let destination = to.unwrap_or_else(|| entry.original_path.clone());
+    let destination = Path::new(&destination);
+
+    if stream.lstat(destination).is_ok() {
+        return Err(anyhow::Error::msg("destination already exists"));
+    }
+
+    stream.rename(&entry_path(&entry.id), destination, None)?;
+    stream.unlink(&meta_path(&entry.id)).ok();
+
+    Ok(entry)
+}
+
+/// Permanently removes entities from the trash
+///
+/// * `id` - removes only the entity with this id
+/// * `deleted_before` - removes only the entities deleted before this timestamp
+///
+/// Returns number of the removed entities
+pub fn purge_trash(
+    stream: &Sftp,
+    id: Option<&str>,
+    deleted_before: Option<i64>,
+) -> anyhow::Result<usize> {
+    let entries = match id {
+        Some(id) => vec![read_meta(stream, &meta_path(validate_id(id)?))?],
+        None => list_trash(stream)?,
+    };
+
+    let mut purged = 0;
+    for entry in entries {
+        if matches!(deleted_before, Some(timestamp) if entry.deleted_at >= timestamp) {
+            continue;
+        }
+
+        remove_path(stream, &entry_path(&entry.id), true)?;
+        stream.unlink(&meta_path(&entry.id))?;
+        purged += 1;
+    }
+
+    Ok(purged)
+}
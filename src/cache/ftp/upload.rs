@@ -0,0 +1,103 @@
+use super::replace_file;
+use crate::cache::worker::{Priority, SessionFile, SessionWorker};
+use crate::utils::throttle::Limiter;
+use ssh2::{OpenFlags, OpenType, Sftp};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Used in upload cache system for saving the progress of the resumable upload.
+///
+/// The content is written into the temporary file next to the destination,
+/// which replaces the destination once the upload is complete
+#[derive(Clone, Debug)]
+pub struct UploadInfo {
+    pub path: String,
+    pub temp_path: String,
+    pub length: u64,
+    pub offset: u64,
+
+    /// set while the content is being written, only one write at a time is allowed
+    pub busy: bool,
+    pub last_usage_timestamp: i64,
+}
+
+impl UploadInfo {
+    pub fn new(path: String, token: &str, length: u64) -> Self {
+        Self {
+            temp_path: upload_temp_path(Path::new(&path), token)
+                .to_string_lossy()
+                .to_string(),
+            path,
+            length,
+            offset: 0,
+            busy: false,
+            last_usage_timestamp: chrono::Local::now().timestamp(),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset >= self.length
+    }
+}
+
+/// Hidden file in the destination directory, so the final rename doesn't cross filesystems
+pub fn upload_temp_path(path: &Path, token: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{token}.part"))
+}
+
+/// Creates the empty temporary file of the upload
+pub fn create_upload(stream: &Sftp, temp_path: &Path) -> anyhow::Result<()> {
+    stream.create(temp_path)?;
+    Ok(())
+}
+
+/// Writes the received chunks into the temporary file starting at the offset.
+///
+/// Returns the offset after the last written chunk,
+/// which is less than expected if the sender has been dropped early.
+/// Every write is queued on the session worker separately,
+/// so no thread waits for the client while it's sending the body.
+pub async fn write_upload(
+    worker: &SessionWorker,
+    temp_path: &Path,
+    mut offset: u64,
+    mut chunks: mpsc::Receiver<Vec<u8>>,
+    limiter: Limiter,
+) -> anyhow::Result<u64> {
+    let temp_path = temp_path.to_path_buf();
+    let file = worker
+        .run(Priority::Bulk, move |stream| {
+            stream.open_mode(&temp_path, OpenFlags::WRITE, 0o644, OpenType::File)
+        })
+        .await??;
+    let file = SessionFile::new(worker.clone(), file, Priority::Bulk);
+    file.seek_to(offset).await?;
+
+    while let Some(chunk) = chunks.recv().await {
+        limiter.consume(chunk.len() as u64).await;
+        let size = chunk.len() as u64;
+        file.write_chunk(chunk).await?;
+        offset += size;
+    }
+
+    file.flush_writes().await?;
+    Ok(offset)
+}
+
+/// Replaces the destination with the uploaded file,
+/// the overwritten file is kept as a previous version if versioning is enabled.
+///
+/// The destination is left untouched if the upload cannot be moved in its place.
+pub fn finish_upload(stream: &Sftp, temp_path: &Path, path: &Path) -> anyhow::Result<()> {
+    replace_file(stream, temp_path, path)
+}
+
+/// Removes the temporary file of the abandoned upload
+pub fn discard_upload(stream: &Sftp, temp_path: &Path) -> anyhow::Result<()> {
+    stream.unlink(temp_path)?;
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::utils::time;
+use crate::CONFIG;
+use ssh2::{FileType, Sftp};
+use std::path::{Path, PathBuf};
+
+/// Hidden directory created next to the versioned file
+pub const VERSIONS_DIR: &str = ".pjatk-versions";
+
+/// Previous version of the file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VersionEntry {
+    /// name of the version file, used to restore it
+    pub version: String,
+    pub created_at: i64,
+    pub size: u64,
+}
+
+/// Returns the versions directory and the name of the file
+fn versions_location(path: &Path) -> anyhow::Result<(PathBuf, String)> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::Error::msg("invalid file path"))?;
+    let dir = path
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(VERSIONS_DIR);
+
+    Ok((dir, name))
+}
+
+/// Lists all the versions of the file, the newest first
+pub fn list_versions(stream: &Sftp, path: &Path) -> anyhow::Result<Vec<VersionEntry>> {
+    let (dir, name) = versions_location(path)?;
+    let prefix = format!("{name}.");
+
+    let listing = match stream.readdir(&dir) {
+        Ok(listing) => listing,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut versions: Vec<VersionEntry> = listing
+        .into_iter()
+        .filter_map(|(path, stat)| {
+            let version = path.file_name()?.to_string_lossy().to_string();
+            let suffix = version.strip_prefix(&prefix)?;
+            let created_at = suffix.split('-').next()?.parse::<i64>().ok()?;
+
+            Some(VersionEntry {
+                version,
+                created_at,
+                size: stat.size.unwrap_or(0),
+            })
+        })
+        .collect();
+
+    versions.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then(b.version.cmp(&a.version))
+    });
+    Ok(versions)
+}
+
+/// Moves the existing file into the versions directory before it gets overwritten
+/// and removes the versions exceeding the retention count.
+///
+/// Does nothing if versioning is disabled or the file doesn't exist.
+pub fn preserve_version(stream: &Sftp, path: &Path) -> anyhow::Result<Option<VersionEntry>> {
+    if !CONFIG.versions_enabled {
+        return Ok(None);
+    }
+
+    store_version(stream, path)
+}
+
+/// Moves the existing file into the versions directory regardless of the config
+fn store_version(stream: &Sftp, path: &Path) -> anyhow::Result<Option<VersionEntry>> {
+    let stat = match stream.lstat(path) {
+        Ok(stat) if stat.file_type() == FileType::RegularFile => stat,
+        _ => return Ok(None),
+    };
+
+    let (dir, name) = versions_location(path)?;
+    if stream.lstat(&dir).is_err() {
+        stream.mkdir(&dir, 0o755)?;
+    }
+
+    let created_at = time::now();
+    let mut version = format!("{name}.{created_at}");
+    let mut suffix = 1;
+    while stream.lstat(&dir.join(&version)).is_ok() {
+        version = format!("{name}.{created_at}-{suffix}");
+        suffix += 1;
+    }
+
+    stream.rename(path, &dir.join(&version), None)?;
+
+    for old in list_versions(stream, path)?
+        .into_iter()
+        .skip(CONFIG.versions_retention)
+    {
+        stream.unlink(&dir.join(&old.version)).ok();
+    }
+
+    Ok(Some(VersionEntry {
+        version,
+        created_at,
+        size: stat.size.unwrap_or(0),
+    }))
+}
+
+/// Moves the version stored by `preserve_version` back in place of the file,
+/// used when the operation which was going to overwrite the file has failed
+pub fn revert_preserved_version(
+    stream: &Sftp,
+    path: &Path,
+    entry: &VersionEntry,
+) -> anyhow::Result<()> {
+    let (dir, _) = versions_location(path)?;
+    stream.rename(&dir.join(&entry.version), path, None)?;
+    Ok(())
+}
+
+/// Replaces the file with one of its versions,
+/// the current content is preserved as a new version
+pub fn restore_version(stream: &Sftp, path: &Path, version: &str) -> anyhow::Result<VersionEntry> {
+    let entry = list_versions(stream, path)?
+        .into_iter()
+        .find(|entry| entry.version == version)
+        .ok_or_else(|| anyhow::Error::msg("version not found"))?;
+
+    let (dir, _) = versions_location(path)?;
+
+    // hide the version from the retention while the current file is being stored
+    let restoring = dir.join(format!(".restoring-{}", entry.version));
+    stream.rename(&dir.join(&entry.version), &restoring, None)?;
+
+    if let Err(error) = store_version(stream, path) {
+        stream
+            .rename(&restoring, &dir.join(&entry.version), None)
+            .ok();
+        return Err(error);
+    }
+    stream.rename(&restoring, path, None)?;
+
+    Ok(entry)
+}
@@ -0,0 +1,205 @@
+use crate::cache::ftp::VERSIONS_DIR;
+use ssh2::FileStat;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub type DirListing = Vec<(PathBuf, FileStat)>;
+
+/// Listings which have to be removed from the cache after the write operation
+#[derive(Clone, Debug)]
+pub enum ListingInvalidation {
+    Dirs(Vec<String>),
+    All,
+}
+
+impl ListingInvalidation {
+    /// Invalidates the directories containing the paths and the paths themselves
+    pub fn paths<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Self {
+        let mut dirs = Vec::new();
+        for path in paths {
+            let path = path.as_ref();
+            if let Some(parent) = path.parent() {
+                dirs.push(normalize(parent));
+            }
+            dirs.push(normalize(path));
+        }
+        Self::Dirs(dirs)
+    }
+}
+
+/// Versions directory which can be modified when the file is overwritten
+pub fn versions_dir(file: impl AsRef<Path>) -> PathBuf {
+    file.as_ref().with_file_name(VERSIONS_DIR)
+}
+
+/// Used as the key, so the same directory is cached once
+fn normalize(dir: &Path) -> String {
+    dir.components()
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Recently read directory listings of a single client.
+///
+/// Listings read while any write operation was in progress are not stored,
+/// as they may already be outdated once the operation ends.
+pub struct ListingCache {
+    listings: HashMap<String, (i64, DirListing)>,
+    ttl: i64,
+
+    /// changes every time a write operation starts or ends
+    generation: u64,
+    writes_in_progress: usize,
+}
+
+impl ListingCache {
+    /// * `ttl` - how long the listings are kept in seconds, the cache is disabled if not positive
+    pub fn new(ttl: i64) -> Self {
+        Self {
+            listings: HashMap::new(),
+            ttl,
+            generation: 0,
+            writes_in_progress: 0,
+        }
+    }
+
+    /// Returns the listing if it's younger than the TTL and no write is in progress
+    pub fn get(&self, dir: &str, now: i64) -> Option<DirListing> {
+        if self.writes_in_progress > 0 {
+            return None;
+        }
+
+        self.listings
+            .get(&normalize(Path::new(dir)))
+            .filter(|(read_at, _)| now - read_at < self.ttl)
+            .map(|(_, listing)| listing.clone())
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Stores the listing read when the cache was in the `generation`
+    pub fn insert(&mut self, dir: &str, listing: DirListing, generation: u64, now: i64) {
+        if self.ttl <= 0 || self.writes_in_progress > 0 || self.generation != generation {
+            return;
+        }
+
+        self.listings
+            .insert(normalize(Path::new(dir)), (now, listing));
+    }
+
+    pub fn begin_write(&mut self, invalidation: &ListingInvalidation) {
+        self.invalidate(invalidation);
+        self.writes_in_progress += 1;
+        self.generation += 1;
+    }
+
+    pub fn end_write(&mut self, invalidation: &ListingInvalidation) {
+        self.invalidate(invalidation);
+        self.writes_in_progress = self.writes_in_progress.saturating_sub(1);
+        self.generation += 1;
+    }
+
+    pub fn invalidate(&mut self, invalidation: &ListingInvalidation) {
+        match invalidation {
+            ListingInvalidation::Dirs(dirs) => dirs.iter().for_each(|dir| {
+                self.listings.remove(dir);
+            }),
+            ListingInvalidation::All => self.listings.clear(),
+        }
+    }
+
+    /// Removes the expired listings
+    pub fn clean(&mut self, now: i64) {
+        self.listings
+            .retain(|_, (read_at, _)| now - *read_at < self.ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::prelude::v1::test;
+
+    const TTL: i64 = 10;
+
+    fn cached(cache: &ListingCache, dir: &str, now: i64) -> bool {
+        cache.get(dir, now).is_some()
+    }
+
+    #[test]
+    fn listing_expires_after_ttl() {
+        let mut cache = ListingCache::new(TTL);
+        cache.insert("/home", Vec::new(), cache.generation(), 100);
+        assert!(cached(&cache, "/home", 100 + TTL - 1));
+        assert!(!cached(&cache, "/home", 100 + TTL));
+
+        cache.clean(100 + TTL);
+        assert!(!cached(&cache, "/home", 100));
+    }
+
+    #[test]
+    fn same_directory_is_cached_once() {
+        let mut cache = ListingCache::new(TTL);
+        cache.insert("/home//user/", Vec::new(), cache.generation(), 0);
+        assert!(cached(&cache, "/home/user", 0));
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let mut cache = ListingCache::new(0);
+        cache.insert("/home", Vec::new(), cache.generation(), 0);
+        assert!(!cached(&cache, "/home", 0));
+    }
+
+    #[test]
+    fn listing_read_before_the_write_is_not_stored() {
+        let mut cache = ListingCache::new(TTL);
+        let invalidation = ListingInvalidation::paths(["/home/file"]);
+
+        // read started before the write, finished after it
+        let generation = cache.generation();
+        cache.begin_write(&invalidation);
+        cache.end_write(&invalidation);
+        cache.insert("/home", Vec::new(), generation, 0);
+        assert!(!cached(&cache, "/home", 0));
+
+        cache.insert("/home", Vec::new(), cache.generation(), 0);
+        assert!(cached(&cache, "/home", 0));
+    }
+
+    #[test]
+    fn nothing_is_cached_while_writing() {
+        let mut cache = ListingCache::new(TTL);
+        cache.insert("/other", Vec::new(), cache.generation(), 0);
+
+        let invalidation = ListingInvalidation::paths(["/home/file"]);
+        cache.begin_write(&invalidation);
+        cache.insert("/home", Vec::new(), cache.generation(), 0);
+        assert!(!cached(&cache, "/home", 0));
+        assert!(!cached(&cache, "/other", 0));
+
+        cache.end_write(&invalidation);
+        assert!(cached(&cache, "/other", 0));
+    }
+
+    #[test]
+    fn write_invalidates_the_parent_and_the_path() {
+        let mut cache = ListingCache::new(TTL);
+        for dir in ["/home", "/home/dir", "/other"] {
+            cache.insert(dir, Vec::new(), cache.generation(), 0);
+        }
+
+        let invalidation = ListingInvalidation::paths(["/home/dir"]);
+        cache.begin_write(&invalidation);
+        cache.end_write(&invalidation);
+        assert!(!cached(&cache, "/home", 0));
+        assert!(!cached(&cache, "/home/dir", 0));
+        assert!(cached(&cache, "/other", 0));
+
+        cache.invalidate(&ListingInvalidation::All);
+        assert!(!cached(&cache, "/other", 0));
+    }
+}
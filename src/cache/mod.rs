@@ -3,12 +3,26 @@
 mod cached_value;
 mod cores;
 mod directives;
+pub mod disk;
 pub mod ftp;
+mod listing;
+pub mod share;
+mod worker;
 
 use crate::cache::cores::ftp_cache::FtpSender;
 use crate::cache::cores::transfer_cache::TransferSender;
-use crate::cache::ftp::{FileContentPack, NoCallback, TransferID, TransferInfo};
+use crate::cache::disk::DiskCacheLookup;
+use crate::cache::ftp::{
+    BatchOperation, BatchReport, ChunkSize, ConnectionSlot, DirComparison, FileContentPack,
+    FileDiff, FileStream, NoCallback, StreamRequest, TransferID, TransferInfo, TransferStatus,
+    TrashEntry, UploadInfo, VersionEntry,
+};
+use crate::cache::listing::DirListing;
+use crate::cache::share::{ShareInfo, ShareLink};
 use crate::handler::endpoints::login::LoginData;
+use crate::utils::throttle::{Direction, Limiter};
+use crate::utils::time;
+use crate::CONFIG;
 
 use crate::logging::*;
 use cached_value::*;
@@ -19,10 +33,9 @@ use ssh2::{FileStat, Sftp};
 
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::io::Read;
 use std::path::PathBuf;
 
-use std::sync::{Arc, RwLockWriteGuard};
+use std::sync::Arc;
 
 use tokio::spawn;
 
@@ -33,11 +46,22 @@ use tokio::sync::oneshot;
 const FTP_CACHE_CAPACITY: usize = 256;
 const FTP_CLEANING_DELAY_S: i64 = 60;
 const FTP_CLIENT_LIFETIME_S: i64 = 60 * 5;
+const FTP_ADDITIONAL_SESSION_LIFETIME_S: i64 = 60;
 
 const TRANSFER_CACHE_CAPACITY: usize = 1024;
 const TRANSFER_CLEANING_DELAY_S: i64 = 40;
 const TRANSFER_LIFETIME_S: i64 = 30;
 
+const TRASH_CLEANING_DELAY_S: i64 = 60 * 60;
+
+const SHARE_CACHE_CAPACITY: usize = 256;
+const SHARE_CLEANING_DELAY_S: i64 = 60;
+
+const UPLOAD_CACHE_CAPACITY: usize = 256;
+const UPLOAD_CLEANING_DELAY_S: i64 = 60 * 5;
+
+const DISK_CACHE_CAPACITY: usize = 256;
+
 pub type FtpClientID = Arc<String>;
 
 lazy_static! {
@@ -53,6 +77,24 @@ lazy_static! {
         spawn(cores::transfer_cache::handler(rx));
         tx
     };
+    static ref SENDER_SHARE: Sender<DirectiveShare> = {
+        info!("Share cache has been initialized");
+        let (tx, rx) = mpsc::channel(SHARE_CACHE_CAPACITY);
+        spawn(cores::share_cache::handler(rx));
+        tx
+    };
+    static ref SENDER_UPLOAD: Sender<DirectiveUpload> = {
+        info!("Upload cache has been initialized");
+        let (tx, rx) = mpsc::channel(UPLOAD_CACHE_CAPACITY);
+        spawn(cores::upload_cache::handler(rx));
+        tx
+    };
+    static ref SENDER_DISK: Sender<DirectiveDiskCache> = {
+        info!("Disk cache has been initialized");
+        let (tx, rx) = mpsc::channel(DISK_CACHE_CAPACITY);
+        spawn(cores::disk_cache::handler(rx));
+        tx
+    };
 }
 
 /// Collection of functions to perform specific operations on the cache system
@@ -66,13 +108,22 @@ impl Cache {
     pub async fn init() {
         let _ = SENDER_FTP.clone();
         let _ = SENDER_TRANSFER.clone();
+        let _ = SENDER_SHARE.clone();
+        let _ = SENDER_UPLOAD.clone();
+        let _ = SENDER_DISK.clone();
         cleanup().await;
     }
 
-    fn add_ftp_client_blocking(id: FtpClientID, stream: Sftp) -> FtpClientID {
+    fn add_ftp_client_blocking(
+        id: FtpClientID,
+        (stream, slot): (Sftp, ConnectionSlot),
+        login_data: LoginData,
+    ) -> FtpClientID {
         let _ = SENDER_FTP.blocking_send(DirectiveFTP::SFTPAddClient {
             id: id.clone(),
             stream,
+            slot,
+            login_data,
         });
 
         id
@@ -110,6 +161,40 @@ impl Cache {
         });
     }
 
+    /// Cancels the transfer, returns false if it does not exist
+    pub async fn cancel_transfer(id: FtpClientID, token: impl ToString) -> bool {
+        let mut sender = TransferSender::new();
+        let directive = DirectiveTransfer::CancelTransfer {
+            transfer_id: TransferID::new(id, token),
+            callback: sender.take_callback(),
+        };
+        sender.send_with_callback(directive).await
+    }
+
+    /// Lists the transfers of the client, the oldest first
+    pub async fn list_transfers(id: FtpClientID) -> Vec<TransferStatus> {
+        let mut sender = TransferSender::new();
+        let directive = DirectiveTransfer::ListTransfers {
+            owner: id,
+            callback: sender.take_callback(),
+        };
+        sender.send_with_callback(directive).await
+    }
+
+    fn list_transfers_blocking(id: FtpClientID) -> Vec<TransferStatus> {
+        let mut sender = TransferSender::new();
+        let directive = DirectiveTransfer::ListTransfers {
+            owner: id,
+            callback: sender.take_callback(),
+        };
+        sender.send_with_callback_blocking(directive)
+    }
+
+    /// Asks for the additional session of the client, used by the large streams
+    pub async fn ftp_request_session(id: FtpClientID) {
+        FtpSender::<NoCallback>::send(DirectiveFTP::SFTPRequestSession { id }).await;
+    }
+
     fn remove_transfer_blocking(transfer_id: TransferID) {
         TransferSender::<NoCallback>::send_blocking(DirectiveTransfer::RemoveTransferInfo {
             transfer_id,
@@ -124,6 +209,300 @@ impl Cache {
         TransferSender::<NoCallback>::send(DirectiveTransfer::Clean { now }).await;
     }
 
+    pub async fn clean_trash(now: i64) {
+        FtpSender::<NoCallback>::send(DirectiveFTP::CleanTrash { now }).await;
+    }
+
+    pub async fn clean_shares(now: i64) {
+        SENDER_SHARE.send(DirectiveShare::Clean { now }).await.ok();
+    }
+
+    /// Sends the share directive and waits for the result
+    async fn share_execute<T>(
+        directive: impl FnOnce(Callback<T>) -> DirectiveShare,
+    ) -> anyhow::Result<T> {
+        let (tx, rx) = oneshot::channel();
+        SENDER_SHARE.send(directive(tx)).await.ok();
+        rx.await
+            .map_err(|_| anyhow::Error::msg("share channel has been closed"))
+    }
+
+    /// Copies the file into the server's storage and creates the share link for it
+    ///
+    /// * `lifetime_s` - how long the link is valid, limited by the config
+    pub async fn share_create(
+        id: FtpClientID,
+        file: impl ToString,
+        lifetime_s: Option<i64>,
+    ) -> anyhow::Result<ShareInfo> {
+        let file = file.to_string();
+        let owner = id.to_string();
+
+        let (count, total_size) = Self::share_execute(|callback| DirectiveShare::Usage {
+            owner: owner.clone(),
+            callback,
+        })
+        .await?;
+        if count >= CONFIG.share_max_links_per_user {
+            return Err(anyhow::Error::msg("too many active share links"));
+        }
+        let max_size = CONFIG
+            .share_max_file_size
+            .min(CONFIG.share_max_total_size.saturating_sub(total_size));
+
+        let name = std::path::Path::new(&file)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .ok_or_else(|| anyhow::Error::msg("invalid file path"))?;
+        let share_id = share::generate_id();
+
+        let size = Self::ftp_execute(id, |callback| DirectiveExecuteFTP::SnapshotFile {
+            file,
+            destination: share::data_path(&share_id),
+            max_size,
+            callback,
+        })
+        .await?;
+
+        let now = time::now();
+        let lifetime_s = lifetime_s
+            .unwrap_or(CONFIG.share_default_lifetime_s)
+            .clamp(1, CONFIG.share_max_lifetime_s);
+        let link = ShareLink {
+            id: share_id,
+            owner,
+            name,
+            size,
+            created_at: now,
+            expires_at: now + lifetime_s,
+        };
+        let info = ShareInfo::from(&link);
+
+        let saved_link = link.clone();
+        let saved = tokio::task::spawn_blocking(move || share::save_link(&saved_link))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::Error::msg("cannot save the share link")));
+
+        let added = match saved {
+            Ok(_) => {
+                let link = link.clone();
+                Self::share_execute(|callback| DirectiveShare::Add { link, callback })
+                    .await
+                    .and_then(|result| result)
+            }
+            Err(error) => Err(error),
+        };
+
+        if let Err(error) = added {
+            tokio::task::spawn_blocking(move || share::remove_link_files(&link.id));
+            return Err(error);
+        }
+
+        Ok(info)
+    }
+
+    pub async fn share_get(share_id: impl ToString) -> Option<ShareLink> {
+        let id = share_id.to_string();
+        Self::share_execute(|callback| DirectiveShare::Get { id, callback })
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn share_list(id: FtpClientID) -> Vec<ShareInfo> {
+        let owner = id.to_string();
+        Self::share_execute(|callback| DirectiveShare::List { owner, callback })
+            .await
+            .unwrap_or_default()
+            .iter()
+            .map(ShareInfo::from)
+            .collect()
+    }
+
+    /// Returns false if the link doesn't exist or belongs to someone else
+    pub async fn share_revoke(id: FtpClientID, share_id: impl ToString) -> bool {
+        let owner = id.to_string();
+        let id = share_id.to_string();
+        Self::share_execute(|callback| DirectiveShare::Revoke {
+            owner,
+            id,
+            callback,
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    pub async fn clean_uploads(now: i64) {
+        SENDER_UPLOAD
+            .send(DirectiveUpload::Clean { now })
+            .await
+            .ok();
+    }
+
+    /// Creates the temporary file of the resumable upload and starts tracking its offset
+    ///
+    /// Returns the token of the upload.
+    pub async fn upload_create(
+        id: FtpClientID,
+        file: impl ToString,
+        length: u64,
+    ) -> anyhow::Result<String> {
+        let upload_id = TransferID::generate(id.clone());
+        let upload_info = UploadInfo::new(file.to_string(), upload_id.token(), length);
+
+        let temp_file = upload_info.temp_path.clone();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::CreateUpload {
+            temp_file,
+            callback,
+        })
+        .await?;
+
+        let token = upload_id.token().to_string();
+        SENDER_UPLOAD
+            .send(DirectiveUpload::AddUploadInfo {
+                upload_id,
+                upload_info,
+            })
+            .await
+            .ok();
+
+        Ok(token)
+    }
+
+    pub async fn upload_get(id: FtpClientID, token: impl ToString) -> Option<UploadInfo> {
+        let (tx, rx) = oneshot::channel();
+        SENDER_UPLOAD
+            .send(DirectiveUpload::GetUploadInfo {
+                upload_id: TransferID::new(id, token),
+                callback: tx,
+            })
+            .await
+            .ok();
+        rx.await.ok().flatten()
+    }
+
+    /// Marks the upload as busy, returns its state from before the call.
+    ///
+    /// If the returned upload was already busy, it must not be released by the caller.
+    pub async fn upload_acquire(id: FtpClientID, token: impl ToString) -> Option<UploadInfo> {
+        let (tx, rx) = oneshot::channel();
+        SENDER_UPLOAD
+            .send(DirectiveUpload::AcquireUpload {
+                upload_id: TransferID::new(id, token),
+                callback: tx,
+            })
+            .await
+            .ok();
+        rx.await.ok().flatten()
+    }
+
+    pub async fn upload_release(id: FtpClientID, token: impl ToString, offset: u64) {
+        SENDER_UPLOAD
+            .send(DirectiveUpload::ReleaseUpload {
+                upload_id: TransferID::new(id, token),
+                offset,
+            })
+            .await
+            .ok();
+    }
+
+    pub async fn upload_remove(id: FtpClientID, token: impl ToString) {
+        SENDER_UPLOAD
+            .send(DirectiveUpload::RemoveUploadInfo {
+                upload_id: TransferID::new(id, token),
+            })
+            .await
+            .ok();
+    }
+
+    /// Removes the idle uploads of the client, so their temporary files can be discarded before it's logged-off
+    pub async fn upload_take_client(id: FtpClientID) -> Vec<UploadInfo> {
+        let (tx, rx) = oneshot::channel();
+        SENDER_UPLOAD
+            .send(DirectiveUpload::TakeClientUploads {
+                owner: id,
+                callback: tx,
+            })
+            .await
+            .ok();
+        rx.await.unwrap_or_default()
+    }
+
+    pub async fn upload_add_leftovers(id: FtpClientID, temp_paths: Vec<String>) {
+        SENDER_UPLOAD
+            .send(DirectiveUpload::AddLeftovers {
+                owner: id,
+                temp_paths,
+            })
+            .await
+            .ok();
+    }
+
+    fn upload_add_leftovers_blocking(id: FtpClientID, temp_paths: Vec<String>) {
+        SENDER_UPLOAD
+            .blocking_send(DirectiveUpload::AddLeftovers {
+                owner: id,
+                temp_paths,
+            })
+            .ok();
+    }
+
+    /// Temporary files of the uploads which could not be removed while the client was logged-off
+    pub async fn upload_take_leftovers(id: FtpClientID) -> Vec<String> {
+        let (tx, rx) = oneshot::channel();
+        SENDER_UPLOAD
+            .send(DirectiveUpload::TakeLeftovers {
+                owner: id,
+                callback: tx,
+            })
+            .await
+            .ok();
+        rx.await.unwrap_or_default()
+    }
+
+    /// Writes the chunks into the temporary file of the upload, returns the new offset
+    pub async fn ftp_write_upload(
+        id: FtpClientID,
+        temp_file: impl ToString,
+        offset: u64,
+        chunks: mpsc::Receiver<Vec<u8>>,
+    ) -> anyhow::Result<u64> {
+        let temp_file = temp_file.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::WriteUpload {
+            temp_file,
+            offset,
+            chunks,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_finish_upload(
+        id: FtpClientID,
+        temp_file: impl ToString,
+        file: impl ToString,
+    ) -> anyhow::Result<()> {
+        let (temp_file, file) = (temp_file.to_string(), file.to_string());
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::FinishUpload {
+            temp_file,
+            file,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_discard_upload(
+        id: FtpClientID,
+        temp_file: impl ToString,
+    ) -> anyhow::Result<()> {
+        let temp_file = temp_file.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::DiscardUpload {
+            temp_file,
+            callback,
+        })
+        .await
+    }
+
     pub async fn ftp_client_exists(id: impl ToString) -> bool {
         let mut sender = FtpSender::new();
         let directive = DirectiveFTP::SFTPClientExists {
@@ -133,6 +512,16 @@ impl Cache {
         sender.send_with_callback(directive).await
     }
 
+    /// Returns credentials of the connected client
+    pub async fn ftp_credentials(id: FtpClientID) -> Option<LoginData> {
+        let mut sender = FtpSender::new();
+        let directive = DirectiveFTP::SFTPCredentials {
+            id,
+            callback: sender.take_callback(),
+        };
+        sender.send_with_callback(directive).await
+    }
+
     pub async fn ftp_connect(login_data: LoginData) -> anyhow::Result<FtpClientID> {
         let mut sender = FtpSender::new();
         let directive = DirectiveFTP::SFTPConnect {
@@ -142,10 +531,11 @@ impl Cache {
         sender.send_with_callback(directive).await
     }
 
-    pub async fn ftp_read_dir(
+    /// Executes the ftp directive on the client's session and waits for the result
+    async fn ftp_execute<T>(
         id: FtpClientID,
-        dir: impl ToString,
-    ) -> anyhow::Result<Vec<(PathBuf, FileStat)>> {
+        ftp_directive: impl FnOnce(Callback<anyhow::Result<T>>) -> DirectiveExecuteFTP,
+    ) -> anyhow::Result<T> {
         let (tx_check, rx_check) = oneshot::channel();
         let (tx, rx) = oneshot::channel();
 
@@ -153,29 +543,306 @@ impl Cache {
             .send(DirectiveFTP::SFTPExecute {
                 id,
                 callback: tx_check,
-                ftp_directive: DirectiveExecuteFTP::ReadDir {
-                    dir: dir.to_string(),
-                    callback: tx,
-                },
+                ftp_directive: ftp_directive(tx),
             })
             .await;
 
         if rx_check.await.unwrap() {
-            rx.await.unwrap()
+            rx.await
+                .unwrap_or_else(|_| Err(anyhow::Error::msg("ftp channel has been closed")))
         } else {
             Err(anyhow::Error::msg("invalid ftp client id"))
         }
     }
 
+    /// Lists the directory, the recently read listing is returned from the cache
+    /// unless `refresh` is set
+    pub async fn ftp_read_dir(
+        id: FtpClientID,
+        dir: impl ToString,
+        refresh: bool,
+    ) -> anyhow::Result<DirListing> {
+        let dir = dir.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::ReadDir {
+            dir,
+            refresh,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_diff_files(
+        id: FtpClientID,
+        left: impl ToString,
+        right: impl ToString,
+        ignore_whitespace: bool,
+        context: usize,
+    ) -> anyhow::Result<FileDiff> {
+        let (left, right) = (left.to_string(), right.to_string());
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::DiffFiles {
+            left,
+            right,
+            ignore_whitespace,
+            context,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_compare_dirs(
+        id: FtpClientID,
+        left: impl ToString,
+        right: impl ToString,
+        hash: bool,
+    ) -> anyhow::Result<DirComparison> {
+        let (left, right) = (left.to_string(), right.to_string());
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::CompareDirs {
+            left,
+            right,
+            hash,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_batch(
+        id: FtpClientID,
+        operations: Vec<BatchOperation>,
+        stop_on_error: bool,
+    ) -> anyhow::Result<BatchReport> {
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::Batch {
+            operations,
+            stop_on_error,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_list_trash(id: FtpClientID) -> anyhow::Result<Vec<TrashEntry>> {
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::ListTrash { callback }).await
+    }
+
+    pub async fn ftp_restore_trash(
+        id: FtpClientID,
+        trash_id: impl ToString,
+        to: Option<String>,
+    ) -> anyhow::Result<TrashEntry> {
+        let trash_id = trash_id.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::RestoreTrash {
+            trash_id,
+            to,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_purge_trash(
+        id: FtpClientID,
+        trash_id: Option<String>,
+    ) -> anyhow::Result<usize> {
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::PurgeTrash {
+            trash_id,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_list_versions(
+        id: FtpClientID,
+        file: impl ToString,
+    ) -> anyhow::Result<Vec<VersionEntry>> {
+        let file = file.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::ListVersions {
+            file,
+            callback,
+        })
+        .await
+    }
+
+    pub async fn ftp_restore_version(
+        id: FtpClientID,
+        file: impl ToString,
+        version: impl ToString,
+    ) -> anyhow::Result<VersionEntry> {
+        let (file, version) = (file.to_string(), version.to_string());
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::RestoreVersion {
+            file,
+            version,
+            callback,
+        })
+        .await
+    }
+
+    /// Opens the file and returns its metadata, fails if the client cannot read the file
+    pub async fn ftp_open_stat(id: FtpClientID, file: impl ToString) -> anyhow::Result<FileStat> {
+        let file = file.to_string();
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::OpenStat {
+            file,
+            callback,
+        })
+        .await
+    }
+
+    /// Opens the file stream, the content is read in the background.
+    ///
+    /// Files in the shared roots are streamed from the disk cache if the local copy is up to date.
+    pub async fn ftp_stream_file(
+        id: FtpClientID,
+        file: impl ToString,
+        request: StreamRequest,
+    ) -> anyhow::Result<FileStream> {
+        let file = file.to_string();
+        if disk::is_cached_path(&file) {
+            if let Some(file_stream) =
+                Self::disk_cache_stream(id.clone(), &file, request.clone()).await
+            {
+                return Ok(file_stream);
+            }
+        }
+
+        Self::ftp_execute(id, |callback| DirectiveExecuteFTP::StreamFile {
+            file,
+            request,
+            callback,
+        })
+        .await
+    }
+
+    /// Streams the local copy of the file after checking its current version with a stat.
+    /// The file is opened by the client first, so the copy is served only if the client can read it.
+    ///
+    /// Returns None if the file has to be read from the sftp server
+    async fn disk_cache_stream(
+        id: FtpClientID,
+        file: &str,
+        request: StreamRequest,
+    ) -> Option<FileStream> {
+        let stat = Self::ftp_open_stat(id.clone(), file)
+            .await
+            .ok()
+            .filter(|stat| !stat.is_dir())?;
+        let (size, modified) = (stat.size.unwrap_or(0), stat.mtime.unwrap_or(0) as i64);
+        let local = Self::disk_cache_lookup(id.clone(), file, size, modified).await?;
+
+        let (tx, rx) = oneshot::channel();
+        let path = file.to_string();
+        spawn(async move {
+            let limiter = Limiter::new(Direction::Download, Some(id));
+            ftp::stream_local_file(&local, path, size, modified, request, limiter, tx).await;
+        });
+
+        // the local copy may have been evicted in the meantime
+        rx.await.ok()?.ok()
+    }
+
+    /// Returns the local copy of the file version,
+    /// the copy is downloaded in the background if the file is not cached yet
+    async fn disk_cache_lookup(
+        id: FtpClientID,
+        file: &str,
+        size: u64,
+        modified: i64,
+    ) -> Option<PathBuf> {
+        let key = disk::cache_key(file, size, modified);
+        let (tx, rx) = oneshot::channel();
+        SENDER_DISK
+            .send(DirectiveDiskCache::Lookup {
+                key: key.clone(),
+                size,
+                callback: tx,
+            })
+            .await
+            .ok();
+
+        Self::disk_cache_resolve(id, file, key, size, rx.await.ok()?)
+    }
+
+    fn disk_cache_lookup_blocking(
+        id: FtpClientID,
+        file: &str,
+        size: u64,
+        modified: i64,
+    ) -> Option<PathBuf> {
+        let key = disk::cache_key(file, size, modified);
+        let (tx, rx) = oneshot::channel();
+        SENDER_DISK
+            .blocking_send(DirectiveDiskCache::Lookup {
+                key: key.clone(),
+                size,
+                callback: tx,
+            })
+            .ok();
+
+        Self::disk_cache_resolve(id, file, key, size, rx.blocking_recv().ok()?)
+    }
+
+    fn disk_cache_resolve(
+        id: FtpClientID,
+        file: &str,
+        key: String,
+        size: u64,
+        lookup: DiskCacheLookup,
+    ) -> Option<PathBuf> {
+        match lookup {
+            DiskCacheLookup::Hit(local) => Some(local),
+            DiskCacheLookup::Fill => {
+                spawn(Self::disk_cache_fill(id, file.to_string(), key, size));
+                None
+            }
+            DiskCacheLookup::Miss => None,
+        }
+    }
+
+    /// Downloads the file version into the disk cache
+    async fn disk_cache_fill(id: FtpClientID, file: String, key: String, size: u64) {
+        let temp = disk::temp_path(&key);
+        let copied = Self::ftp_execute(id, |callback| DirectiveExecuteFTP::SnapshotFile {
+            file,
+            destination: temp.clone(),
+            max_size: size,
+            callback,
+        })
+        .await;
+
+        // the file could have been modified during the download
+        let stored = match copied {
+            Ok(copied) if copied == size => tokio::fs::rename(&temp, disk::data_path(&key))
+                .await
+                .is_ok(),
+            Ok(_) => {
+                tokio::fs::remove_file(&temp).await.ok();
+                false
+            }
+            Err(_) => false,
+        };
+
+        let directive = if stored {
+            DirectiveDiskCache::Insert { key, size }
+        } else {
+            DirectiveDiskCache::Abort { key }
+        };
+        SENDER_DISK.send(directive).await.ok();
+    }
+
+    /// Reads the next pack of the file transfer identified by the token,
+    /// new transfer is started if the token is not provided.
+    ///
+    /// The pack which has already been sent can be read again by its number.
     pub async fn ftp_read_file(
         id: FtpClientID,
         file: impl ToString,
+        transfer: Option<String>,
+        chunk_size: Option<ChunkSize>,
+        pack: Option<u64>,
     ) -> anyhow::Result<FileContentPack> {
         let (tx_check, rx_check) = oneshot::channel();
         let (tx, rx) = oneshot::channel();
 
-        let filename = file.to_string();
-        let transfer_id = TransferID::new(id.clone(), &filename);
+        // continued transfers never open the file again
+        let (transfer_id, filename) = match transfer {
+            Some(token) => (TransferID::new(id.clone(), token), None),
+            None => (TransferID::generate(id.clone()), Some(file.to_string())),
+        };
 
         let _ = SENDER_FTP
             .send(DirectiveFTP::SFTPExecute {
@@ -183,7 +850,9 @@ impl Cache {
                 callback: tx_check,
                 ftp_directive: DirectiveExecuteFTP::TransferFile {
                     transfer_id: transfer_id.clone(),
-                    filename: Some(filename),
+                    filename,
+                    chunk_size,
+                    pack,
                     callback: tx,
                 },
             })
@@ -191,16 +860,7 @@ impl Cache {
 
         if rx_check.await.unwrap() {
             if let Ok(content_pack) = rx.await {
-                let content_pack = content_pack?;
-
-                if content_pack.is_last {
-                    SENDER_TRANSFER
-                        .send(DirectiveTransfer::RemoveTransferInfo { transfer_id })
-                        .await
-                        .ok();
-                }
-
-                Ok(content_pack)
+                content_pack
             } else {
                 SENDER_TRANSFER
                     .send(DirectiveTransfer::RemoveTransferInfo { transfer_id })
@@ -0,0 +1,124 @@
+use crate::CONFIG;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// File snapshot shared through the public link.
+///
+/// Saved next to the snapshot in the share directory, so the links survive restarts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShareLink {
+    pub id: String,
+    pub owner: String,
+    pub name: String,
+    pub size: u64,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+/// Share link as presented to its owner
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShareInfo {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub url: String,
+}
+
+impl From<&ShareLink> for ShareInfo {
+    fn from(link: &ShareLink) -> Self {
+        Self {
+            id: link.id.clone(),
+            name: link.name.clone(),
+            size: link.size,
+            created_at: link.created_at,
+            expires_at: link.expires_at,
+            url: link.url(),
+        }
+    }
+}
+
+impl ShareLink {
+    /// Public url with the expiration time and the signature
+    pub fn url(&self) -> String {
+        format!(
+            "{}/bin/share/{}?expires={}&signature={}",
+            CONFIG.host,
+            self.id,
+            self.expires_at,
+            sign(&self.id, self.expires_at)
+        )
+    }
+}
+
+/// Generates random, url safe id of the share link
+pub fn generate_id() -> String {
+    let mut bytes = [0u8; 18];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn mac(id: &str, expires_at: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(CONFIG.share_secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(format!("{id}:{expires_at}").as_bytes());
+    mac
+}
+
+/// Signs the id and the expiration time of the link with the server's secret
+pub fn sign(id: &str, expires_at: i64) -> String {
+    base64::encode_config(
+        mac(id, expires_at).finalize().into_bytes(),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Checks the signature in constant time
+pub fn verify(id: &str, expires_at: i64, signature: &str) -> bool {
+    match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+        Ok(signature) => mac(id, expires_at).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Path of the file snapshot
+pub fn data_path(id: &str) -> PathBuf {
+    Path::new(&CONFIG.share_dir).join(format!("{id}.bin"))
+}
+
+/// Path of the link metadata
+pub fn meta_path(id: &str) -> PathBuf {
+    Path::new(&CONFIG.share_dir).join(format!("{id}.json"))
+}
+
+/// Saves the link metadata into the share directory
+pub fn save_link(link: &ShareLink) -> anyhow::Result<()> {
+    std::fs::write(meta_path(&link.id), serde_json::to_string(link)?)?;
+    Ok(())
+}
+
+/// Removes the snapshot and the metadata of the link
+pub fn remove_link_files(id: &str) {
+    std::fs::remove_file(data_path(id)).ok();
+    std::fs::remove_file(meta_path(id)).ok();
+}
+
+/// Loads all the links saved in the share directory
+pub fn load_links() -> Vec<ShareLink> {
+    let dir = match std::fs::read_dir(&CONFIG.share_dir) {
+        Ok(dir) => dir,
+        Err(_) => return Vec::new(),
+    };
+
+    dir.filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().map(|ext| ext == "json") == Some(true))
+        .filter_map(|entry| std::fs::read(entry.path()).ok())
+        .filter_map(|data| serde_json::from_slice::<ShareLink>(&data).ok())
+        .filter(|link| data_path(&link.id).exists())
+        .collect()
+}
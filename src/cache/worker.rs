@@ -0,0 +1,461 @@
+use crate::cache::ftp::ConnectionSlot;
+use crate::logging::*;
+use crate::utils::time;
+use crate::CONFIG;
+use ssh2::{File, FileStat, Sftp};
+use std::collections::VecDeque;
+use std::io::{Error, Read, Seek, SeekFrom, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Order in which the queued commands are executed by the session worker
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Priority {
+    /// quick commands the user is waiting for, like listing the directory
+    Interactive,
+    Normal,
+    /// single steps of the long transfers
+    Bulk,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+
+    fn index(self) -> usize {
+        match self {
+            Self::Interactive => 0,
+            Self::Normal => 1,
+            Self::Bulk => 2,
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&Sftp) + Send>;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+lazy_static! {
+    /// Queue of the threads driving the long running commands
+    static ref DRIVERS: Mutex<Sender<Task>> = Mutex::new(spawn_drivers());
+}
+
+/// Spawns the `ssh_driver_threads` threads, separate from the tokio blocking pool,
+/// so the long downloads and uploads cannot exhaust it
+fn spawn_drivers() -> Sender<Task> {
+    let (tx, rx) = std::sync::mpsc::channel::<Task>();
+    let rx = Arc::new(Mutex::new(rx));
+
+    // commands use the blocking senders of the caches
+    let runtime = tokio::runtime::Handle::try_current().ok();
+
+    for index in 0..CONFIG.ssh_driver_threads.max(1) {
+        let rx = rx.clone();
+        let runtime = runtime.clone();
+        std::thread::Builder::new()
+            .name(format!("sftp-driver-{index}"))
+            .spawn(move || {
+                let _runtime = runtime.as_ref().map(|runtime| runtime.enter());
+                loop {
+                    let task = match rx.lock().unwrap().recv() {
+                        Ok(task) => task,
+                        Err(_) => return,
+                    };
+
+                    if std::panic::catch_unwind(AssertUnwindSafe(task)).is_err() {
+                        warn!("Long running sftp command has panicked");
+                    }
+                }
+            })
+            .expect("cannot spawn the sftp driver thread");
+    }
+
+    tx
+}
+
+/// Runs the long running command on one of the driver threads,
+/// it waits in the queue while all of them are busy
+pub fn drive(task: impl FnOnce() + Send + 'static) {
+    DRIVERS.lock().unwrap().send(Box::new(task)).ok();
+}
+
+#[derive(Default)]
+struct Queue {
+    jobs: [VecDeque<Job>; Priority::COUNT],
+    running: bool,
+    closed: bool,
+}
+
+impl Queue {
+    fn pop(&mut self) -> Option<Job> {
+        self.jobs.iter_mut().find_map(|jobs| jobs.pop_front())
+    }
+}
+
+/// Dedicated thread which owns the sftp session of a single client.
+///
+/// Commands are executed one at a time in the order of their priority,
+/// so the session is never used from multiple threads at once.
+#[derive(Clone)]
+pub struct SessionWorker {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+
+    /// files opened by the long running commands
+    open_files: Arc<AtomicUsize>,
+
+    /// when the last command has finished
+    last_usage_timestamp: Arc<AtomicI64>,
+}
+
+impl SessionWorker {
+    /// The connection slot is released once the thread stops
+    pub fn spawn(stream: Sftp, slot: ConnectionSlot, name: String) -> Self {
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+        let worker_queue = queue.clone();
+        let last_usage_timestamp = Arc::new(AtomicI64::new(time::now()));
+        let worker_last_usage_timestamp = last_usage_timestamp.clone();
+
+        // jobs can spawn the tokio tasks
+        let runtime = tokio::runtime::Handle::try_current().ok();
+
+        std::thread::Builder::new()
+            .name(format!("sftp-{name}"))
+            .spawn(move || {
+                let _slot = slot;
+                let _runtime = runtime.as_ref().map(|runtime| runtime.enter());
+                let (lock, condvar) = &*worker_queue;
+
+                loop {
+                    let job = {
+                        let mut queue = lock.lock().unwrap();
+                        queue.running = false;
+                        loop {
+                            if let Some(job) = queue.pop() {
+                                queue.running = true;
+                                break job;
+                            }
+                            if queue.closed {
+                                return;
+                            }
+                            queue = condvar.wait(queue).unwrap();
+                        }
+                    };
+
+                    // the panicking command must not stop the session
+                    if std::panic::catch_unwind(AssertUnwindSafe(|| job(&stream))).is_err() {
+                        warn!("Sftp command has panicked");
+                    }
+                    worker_last_usage_timestamp.store(time::now(), Ordering::Relaxed);
+                }
+            })
+            .expect("cannot spawn the sftp worker thread");
+
+        Self {
+            queue,
+            open_files: Arc::new(AtomicUsize::new(0)),
+            last_usage_timestamp,
+        }
+    }
+
+    /// Number of the queued and running commands and the files used by the long running commands
+    pub fn load(&self) -> usize {
+        let queue = self.queue.0.lock().unwrap();
+        let queued: usize = queue.jobs.iter().map(|jobs| jobs.len()).sum();
+        queued + queue.running as usize + self.open_files.load(Ordering::Relaxed)
+    }
+
+    /// Checks if the worker has nothing to do since the given time
+    fn is_idle_since(&self, since: i64) -> bool {
+        self.load() == 0 && self.last_usage_timestamp.load(Ordering::Relaxed) <= since
+    }
+
+    /// Queues the command, it's dropped without being executed if the worker has been closed
+    pub fn submit(&self, priority: Priority, job: impl FnOnce(&Sftp) + Send + 'static) {
+        let (lock, condvar) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        if !queue.closed {
+            queue.jobs[priority.index()].push_back(Box::new(job));
+            condvar.notify_one();
+        }
+    }
+
+    /// Queues the command and waits for its result
+    pub async fn run<T: Send + 'static>(
+        &self,
+        priority: Priority,
+        job: impl FnOnce(&Sftp) -> T + Send + 'static,
+    ) -> std::io::Result<T> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.submit(priority, move |stream| {
+            tx.send(job(stream)).ok();
+        });
+        rx.await
+            .map_err(|_| Error::other("sftp session has been closed"))
+    }
+
+    /// Queues the command and blocks the thread until its result is ready.
+    ///
+    /// Must not be called from the worker thread.
+    pub fn run_blocking<T: Send + 'static>(
+        &self,
+        priority: Priority,
+        job: impl FnOnce(&Sftp) -> T + Send + 'static,
+    ) -> std::io::Result<T> {
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        self.submit(priority, move |stream| {
+            tx.send(job(stream)).ok();
+        });
+        rx.recv()
+            .map_err(|_| Error::other("sftp session has been closed"))
+    }
+
+    /// Stops the thread once the already queued commands are executed
+    pub fn close(&self) {
+        let (lock, condvar) = &*self.queue;
+        lock.lock().unwrap().closed = true;
+        condvar.notify_all();
+    }
+}
+
+/// Remote file used outside of the worker thread,
+/// every operation is queued on the worker as a separate command.
+///
+/// Long reads and writes are split into many commands,
+/// so the other commands of the client don't wait until they are finished.
+/// The async methods are used by the commands paced by the client,
+/// the blocking `Read`, `Write` and `Seek` by the commands driven from the dedicated threads.
+pub struct SessionFile {
+    file: Arc<Mutex<File>>,
+    worker: SessionWorker,
+    priority: Priority,
+}
+
+impl SessionFile {
+    pub fn new(worker: SessionWorker, file: File, priority: Priority) -> Self {
+        worker.open_files.fetch_add(1, Ordering::Relaxed);
+        Self {
+            file: Arc::new(Mutex::new(file)),
+            worker,
+            priority,
+        }
+    }
+
+    /// Opens the file for reading in the worker thread
+    pub async fn open(
+        worker: &SessionWorker,
+        path: &Path,
+        priority: Priority,
+    ) -> anyhow::Result<Self> {
+        let path = path.to_path_buf();
+        let file = worker
+            .run(priority, move |stream| stream.open(&path))
+            .await??;
+        Ok(Self::new(worker.clone(), file, priority))
+    }
+
+    pub fn open_blocking(
+        worker: &SessionWorker,
+        path: &Path,
+        priority: Priority,
+    ) -> anyhow::Result<Self> {
+        let path = path.to_path_buf();
+        let file = worker.run_blocking(priority, move |stream| stream.open(&path))??;
+        Ok(Self::new(worker.clone(), file, priority))
+    }
+
+    /// Executes the operation on the file in the worker thread
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut File) -> std::io::Result<T> + Send + 'static,
+    ) -> std::io::Result<T> {
+        let file = self.file.clone();
+        self.worker
+            .run(self.priority, move |_| operation(&mut file.lock().unwrap()))
+            .await?
+    }
+
+    fn run_blocking<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut File) -> std::io::Result<T> + Send + 'static,
+    ) -> std::io::Result<T> {
+        let file = self.file.clone();
+        self.worker
+            .run_blocking(self.priority, move |_| operation(&mut file.lock().unwrap()))?
+    }
+
+    pub async fn stat(&self) -> anyhow::Result<FileStat> {
+        Ok(self.run(|file| file.stat().map_err(Error::from)).await?)
+    }
+
+    pub fn stat_blocking(&self) -> anyhow::Result<FileStat> {
+        Ok(self.run_blocking(|file| file.stat().map_err(Error::from))?)
+    }
+
+    /// Reads at most `size` bytes, the empty chunk marks the end of the file
+    pub async fn read_chunk(&self, size: usize) -> std::io::Result<Vec<u8>> {
+        self.run(move |file| {
+            let mut data = vec![0u8; size];
+            let read_size = file.read(&mut data)?;
+            data.truncate(read_size);
+            Ok(data)
+        })
+        .await
+    }
+
+    pub async fn write_chunk(&self, data: Vec<u8>) -> std::io::Result<()> {
+        self.run(move |file| file.write_all(&data)).await
+    }
+
+    pub async fn seek_to(&self, offset: u64) -> std::io::Result<()> {
+        self.run(move |file| file.seek(SeekFrom::Start(offset)).map(|_| ()))
+            .await
+    }
+
+    pub async fn flush_writes(&self) -> std::io::Result<()> {
+        self.run(|file| file.flush()).await
+    }
+
+    /// Queues the read of the whole block at the offset without waiting for it
+    pub fn read_block(
+        &self,
+        offset: u64,
+        size: usize,
+    ) -> tokio::sync::oneshot::Receiver<std::io::Result<Vec<u8>>> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let file = self.file.clone();
+        self.worker.submit(self.priority, move |_| {
+            let mut file = file.lock().unwrap();
+            let mut buffer = vec![0u8; size];
+            let block = file
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| file.read_exact(&mut buffer))
+                .map(|_| buffer);
+            tx.send(block).ok();
+        });
+        rx
+    }
+}
+
+impl Read for SessionFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = buf.len();
+        let data = self.run_blocking(move |file| {
+            let mut data = vec![0u8; size];
+            let read_size = file.read(&mut data)?;
+            data.truncate(read_size);
+            Ok(data)
+        })?;
+
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+}
+
+impl Write for SessionFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let data = buf.to_vec();
+        self.run_blocking(move |file| file.write(&data))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.run_blocking(|file| file.flush())
+    }
+}
+
+impl Seek for SessionFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.run_blocking(move |file| file.seek(pos))
+    }
+}
+
+impl Drop for SessionFile {
+    /// Closing the handle requires the session as well
+    fn drop(&mut self) {
+        let file = self.file.clone();
+        self.worker.submit(self.priority, move |_| drop(file));
+        self.worker.open_files.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Sessions of a single client, the commands are distributed across them.
+///
+/// Additional sessions are opened lazily with the same credentials,
+/// when every session already has some work to do or a long running command starts.
+///
+/// The pool is shared with the long running commands, so they can use the sessions opened later.
+#[derive(Clone)]
+pub struct SessionPool {
+    workers: Arc<Mutex<Vec<SessionWorker>>>,
+
+    /// set while the additional session is being opened
+    opening: Arc<AtomicBool>,
+}
+
+impl SessionPool {
+    pub fn new(worker: SessionWorker) -> Self {
+        Self {
+            workers: Arc::new(Mutex::new(vec![worker])),
+            opening: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Number of the opened sessions
+    pub fn sessions(&self) -> usize {
+        self.workers.lock().unwrap().len()
+    }
+
+    /// The least busy session
+    pub fn pick(&self) -> SessionWorker {
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .min_by_key(|worker| worker.load())
+            .cloned()
+            .expect("session pool cannot be empty")
+    }
+
+    /// Checks if the additional session should be opened and marks it as being opened.
+    ///
+    /// The session is opened only if all the sessions are busy, unless it's `requested`.
+    pub fn begin_grow(&self, requested: bool) -> bool {
+        let workers = self.workers.lock().unwrap();
+        let grow = !self.opening.load(Ordering::Relaxed)
+            && workers.len() < CONFIG.ssh_sessions_per_client
+            && (requested || workers.iter().all(|worker| worker.load() > 0));
+        if grow {
+            self.opening.store(true, Ordering::Relaxed);
+        }
+        grow
+    }
+
+    /// Adds the opened session, None if the session could not be opened
+    pub fn end_grow(&self, worker: Option<SessionWorker>) {
+        self.opening.store(false, Ordering::Relaxed);
+        self.workers.lock().unwrap().extend(worker);
+    }
+
+    /// Closes the additional sessions which have been idle for a while,
+    /// so they don't hold the connections of the server. The first session is always kept.
+    pub fn shrink(&self, now: i64) {
+        let idle_since = now - super::FTP_ADDITIONAL_SESSION_LIFETIME_S;
+        let mut workers = self.workers.lock().unwrap();
+        for worker in workers.split_off(1) {
+            if worker.is_idle_since(idle_since) {
+                worker.close();
+            } else {
+                workers.push(worker);
+            }
+        }
+    }
+
+    pub fn close(&self) {
+        self.workers
+            .lock()
+            .unwrap()
+            .iter()
+            .for_each(|worker| worker.close());
+    }
+}
@@ -6,7 +6,10 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 
 /// Main server's config
+///
+/// Missing fields are filled with the default values
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub bind: SocketAddr,
     pub host: String,
@@ -14,6 +17,43 @@ pub struct Config {
     pub ftp_host: SocketAddr,
     #[deprecated]
     pub ftp_domain: String,
+    pub diff_max_file_size: u64,
+    pub compare_max_entries: usize,
+    pub compare_max_hash_size: u64,
+    pub batch_max_operations: usize,
+    pub trash_enabled: bool,
+    pub trash_max_age_s: i64,
+    pub versions_enabled: bool,
+    pub versions_retention: usize,
+    pub terminal_enabled: bool,
+    pub share_dir: String,
+    pub share_secret: String,
+    pub share_max_file_size: u64,
+    pub share_max_total_size: u64,
+    pub share_max_links_per_user: usize,
+    pub share_default_lifetime_s: i64,
+    pub share_max_lifetime_s: i64,
+    pub watch_interval_s: u64,
+    pub transfer_default_chunk_size: usize,
+    pub transfer_max_chunk_size: usize,
+    pub transfer_prefetch_chunks: usize,
+    pub transfer_max_per_client: usize,
+    pub upload_max_size: u64,
+    pub upload_expiration_s: i64,
+    pub download_rate_limit: u64,
+    pub upload_rate_limit: u64,
+    pub session_download_rate_limit: u64,
+    pub session_upload_rate_limit: u64,
+    pub disk_cache_roots: Vec<String>,
+    pub disk_cache_dir: String,
+    pub disk_cache_max_size: u64,
+    pub disk_cache_max_file_size: u64,
+    pub listing_cache_ttl_s: i64,
+    pub ssh_sessions_per_client: usize,
+    pub ssh_max_connections: usize,
+    pub ssh_driver_threads: usize,
+    pub segmented_download_segments: usize,
+    pub segmented_download_min_size: u64,
 }
 
 impl Default for Config {
@@ -24,6 +64,43 @@ impl Default for Config {
             api_version: 1,
             ftp_host: SocketAddr::from_str("91.230.222.36:22").unwrap(),
             ftp_domain: "sftp.pjwstk.edu.pl".to_string(),
+            diff_max_file_size: 1024 * 512,
+            compare_max_entries: 10000,
+            compare_max_hash_size: 1024 * 1024 * 16,
+            batch_max_operations: 256,
+            trash_enabled: false,
+            trash_max_age_s: 60 * 60 * 24 * 30,
+            versions_enabled: false,
+            versions_retention: 10,
+            terminal_enabled: false,
+            share_dir: "shares".to_string(),
+            share_secret: crate::cache::share::generate_id(),
+            share_max_file_size: 1024 * 1024 * 100,
+            share_max_total_size: 1024 * 1024 * 1024 * 2,
+            share_max_links_per_user: 20,
+            share_default_lifetime_s: 60 * 60 * 24,
+            share_max_lifetime_s: 60 * 60 * 24 * 7,
+            watch_interval_s: 5,
+            transfer_default_chunk_size: 1024 * 64,
+            transfer_max_chunk_size: 1024 * 1024 * 4,
+            transfer_prefetch_chunks: 4,
+            transfer_max_per_client: 8,
+            upload_max_size: 1024 * 1024 * 1024 * 4,
+            upload_expiration_s: 60 * 60 * 24,
+            download_rate_limit: 0,
+            upload_rate_limit: 0,
+            session_download_rate_limit: 0,
+            session_upload_rate_limit: 0,
+            disk_cache_roots: Vec::new(),
+            disk_cache_dir: "disk_cache".to_string(),
+            disk_cache_max_size: 1024 * 1024 * 1024 * 2,
+            disk_cache_max_file_size: 1024 * 1024 * 200,
+            listing_cache_ttl_s: 10,
+            ssh_sessions_per_client: 3,
+            ssh_max_connections: 100,
+            ssh_driver_threads: 64,
+            segmented_download_segments: 4,
+            segmented_download_min_size: 1024 * 1024 * 128,
         }
     }
 }
@@ -35,7 +112,18 @@ impl Config {
             .add_source(config::File::new("config", FileFormat::Json))
             .build()
         {
-            Ok(config) => config.try_deserialize::<Config>()?,
+            Ok(config) => {
+                let has_share_secret = config.get_string("share_secret").is_ok();
+                let config = config.try_deserialize::<Config>()?;
+
+                // the generated secret must survive the restart, or all the share links get invalid
+                if !has_share_secret {
+                    warn!("No share secret was found in the config file, saving a generated one.");
+                    Self::save_share_secret(&config.share_secret)?;
+                }
+
+                config
+            }
 
             Err(error) => match &error {
                 ConfigError::Foreign(_) => {
@@ -59,4 +147,16 @@ impl Config {
 
         Ok(cfg)
     }
+
+    /// Adds the share secret into the existing config file, the other fields are kept as they are
+    fn save_share_secret(secret: &str) -> anyhow::Result<()> {
+        let mut fields: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&std::fs::read_to_string("config.json")?)?;
+        fields.insert(
+            "share_secret".to_string(),
+            serde_json::Value::String(secret.to_string()),
+        );
+        std::fs::write("config.json", serde_json::to_string(&fields)?)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,254 @@
+use flate2::write::GzEncoder;
+use hyper::header::ACCEPT_ENCODING;
+use hyper::http::request::Parts;
+use std::io::Write;
+use tokio::sync::mpsc;
+
+/// Brotli quality, the higher levels are too slow for compressing on the fly
+const BROTLI_QUALITY: u32 = 5;
+
+/// Brotli window size (log2)
+const BROTLI_WINDOW: u32 = 22;
+
+/// Size of the brotli internal buffer
+const BROTLI_BUFFER: usize = 4096;
+
+/// How many compressed chunks can wait for the client
+const COMPRESSION_BUFFER: usize = 8;
+
+/// Content encoding supported by the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Picks the encoding with the highest quality from the `Accept-Encoding` header,
+    /// brotli is preferred when both have the same quality.
+    ///
+    /// Returns None if the client doesn't accept any of the supported encodings.
+    pub fn negotiate(meta: &Parts) -> Option<Self> {
+        let header = meta.headers.get(ACCEPT_ENCODING)?.to_str().ok()?;
+
+        let mut gzip = None;
+        let mut brotli = None;
+        let mut wildcard = None;
+        for item in header.split(',') {
+            let mut params = item.split(';');
+            let name = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let quality = params
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|quality| quality.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            match name.as_str() {
+                "gzip" | "x-gzip" => gzip = Some(quality),
+                "br" => brotli = Some(quality),
+                "*" => wildcard = Some(quality),
+                _ => {}
+            }
+        }
+
+        let gzip = gzip.or(wildcard).unwrap_or(0.0);
+        let brotli = brotli.or(wildcard).unwrap_or(0.0);
+        if brotli > 0.0 && brotli >= gzip {
+            Some(Self::Brotli)
+        } else if gzip > 0.0 {
+            Some(Self::Gzip)
+        } else {
+            None
+        }
+    }
+
+    /// Value of the `Content-Encoding` header
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+        }
+    }
+
+    /// ETag of the representation compressed with this encoding
+    pub fn etag(&self, etag: &str) -> String {
+        format!("{}-{}\"", etag.trim_end_matches('"'), self.name())
+    }
+}
+
+/// Checks if the content of the mime type is worth compressing.
+///
+/// Images, videos, audio and archives are already compressed, except for svg.
+pub fn is_compressible(mime: &str) -> bool {
+    let mime = mime.split(';').next().unwrap_or_default().trim();
+    mime.starts_with("text/")
+        || mime.ends_with("+xml")
+        || mime.ends_with("+json")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/rtf"
+                | "application/x-sh"
+                | "application/x-httpd-php"
+        )
+}
+
+/// Streaming compressor, the output is collected while the input is written
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Gzip => {
+                Self::Gzip(GzEncoder::new(Vec::new(), flate2::Compression::default()))
+            }
+            Encoding::Brotli => Self::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+        }
+    }
+
+    /// Compresses the chunk and returns the output produced so far
+    fn compress(&mut self, chunk: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+            Self::Brotli(encoder) => {
+                encoder.write_all(chunk)?;
+                Ok(std::mem::take(encoder.get_mut()))
+            }
+        }
+    }
+
+    /// Finishes the stream and returns the remaining output
+    fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Brotli(encoder) => Ok(encoder.into_inner()),
+        }
+    }
+}
+
+/// Compresses the whole content at once
+pub fn compress(data: &[u8], encoding: Encoding) -> std::io::Result<Vec<u8>> {
+    let mut encoder = Encoder::new(encoding);
+    let mut compressed = encoder.compress(data)?;
+    compressed.append(&mut encoder.finish()?);
+    Ok(compressed)
+}
+
+/// Compresses the chunks from the channel on the blocking thread,
+/// so the whole content doesn't have to be kept in the memory.
+///
+/// The compression stops if any chunk fails or the receiver was dropped.
+pub fn compress_chunks(
+    mut chunks: mpsc::Receiver<anyhow::Result<Vec<u8>>>,
+    encoding: Encoding,
+) -> mpsc::Receiver<anyhow::Result<Vec<u8>>> {
+    let (tx, rx) = mpsc::channel(COMPRESSION_BUFFER);
+
+    tokio::task::spawn_blocking(move || {
+        let mut encoder = Encoder::new(encoding);
+
+        while let Some(chunk) = chunks.blocking_recv() {
+            let compressed = chunk.and_then(|chunk| Ok(encoder.compress(&chunk)?));
+
+            let failed = compressed.is_err();
+            match compressed {
+                // the encoder may buffer the input, nothing to send yet
+                Ok(compressed) if compressed.is_empty() => {}
+                compressed => {
+                    if tx.blocking_send(compressed).is_err() || failed {
+                        return;
+                    }
+                }
+            }
+        }
+
+        tx.blocking_send(encoder.finish().map_err(anyhow::Error::from))
+            .ok();
+    });
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+    use std::prelude::v1::test;
+
+    fn negotiate(accept_encoding: Option<&str>) -> Option<Encoding> {
+        let mut request = Request::builder();
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.header(ACCEPT_ENCODING, accept_encoding);
+        }
+        let (meta, _) = request.body(()).unwrap().into_parts();
+        Encoding::negotiate(&meta)
+    }
+
+    #[test]
+    fn no_header_is_not_compressed() {
+        assert_eq!(negotiate(None), None);
+        assert_eq!(negotiate(Some("")), None);
+        assert_eq!(negotiate(Some("identity, deflate")), None);
+    }
+
+    #[test]
+    fn prefers_brotli_on_equal_quality() {
+        assert_eq!(negotiate(Some("gzip, br")), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate(Some("gzip;q=0.5, br;q=0.5")),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(negotiate(Some("gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("X-GZIP")), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn picks_the_highest_quality() {
+        assert_eq!(
+            negotiate(Some("gzip;q=1.0, br;q=0.8")),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate(Some("gzip;q=0.2, br; q=0.9")),
+            Some(Encoding::Brotli)
+        );
+    }
+
+    #[test]
+    fn zero_quality_rejects_the_encoding() {
+        assert_eq!(negotiate(Some("br;q=0, gzip")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0")), None);
+    }
+
+    #[test]
+    fn wildcard_covers_the_unlisted_encodings() {
+        assert_eq!(negotiate(Some("*")), Some(Encoding::Brotli));
+        assert_eq!(negotiate(Some("br;q=0, *")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("gzip;q=0.5, *;q=0.1")), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("*;q=0")), None);
+    }
+
+    #[test]
+    fn invalid_quality_is_treated_as_default() {
+        assert_eq!(
+            negotiate(Some("gzip;q=abc, br;q=0.5")),
+            Some(Encoding::Gzip)
+        );
+    }
+}
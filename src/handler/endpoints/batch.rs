@@ -0,0 +1,69 @@
+use super::*;
+use crate::cache::ftp::BatchOperation;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::ErrorMessage;
+use crate::CONFIG;
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchData {
+    pub operations: Vec<BatchOperation>,
+    #[serde(default)]
+    pub stop_on_error: bool,
+}
+
+/// API endpoint which executes the list of sftp operations
+/// in order within a single blocking task
+pub struct BatchEndpoint;
+
+#[async_trait]
+impl Endpoint for BatchEndpoint {
+    fn method(&self) -> Option<Method> {
+        Some(Method::POST)
+    }
+
+    fn path(&self) -> &'static str {
+        "batch"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let batch_data = match parsers::body_json::<BatchData>(body).await {
+            Ok(batch_data) => batch_data,
+            Err(error) => return error.to_response(),
+        };
+
+        if batch_data.operations.len() > CONFIG.batch_max_operations {
+            return ErrorMessage::new("too many operations", StatusCode::BAD_REQUEST).to_response();
+        }
+
+        match Cache::ftp_batch(id, batch_data.operations, batch_data.stop_on_error).await {
+            Ok(report) => Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(serde_json::to_string(&report).unwrap()))
+                .unwrap(),
+            Err(error) => ErrorMessage::new(
+                "cannot execute the operations",
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .error_force(error)
+            .to_response(),
+        }
+    }
+}
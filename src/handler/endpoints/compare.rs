@@ -0,0 +1,66 @@
+use super::*;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::ErrorMessage;
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompareData {
+    pub left: String,
+    pub right: String,
+    #[serde(default)]
+    pub hash: bool,
+}
+
+/// API endpoint which walks two directories on the sftp server
+/// and classifies the differences between them
+pub struct CompareEndpoint;
+
+#[async_trait]
+impl Endpoint for CompareEndpoint {
+    fn method(&self) -> Option<Method> {
+        Some(Method::POST)
+    }
+
+    fn path(&self) -> &'static str {
+        "compare"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let compare_data = match parsers::body_json::<CompareData>(body).await {
+            Ok(compare_data) => compare_data,
+            Err(error) => return error.to_response(),
+        };
+
+        match Cache::ftp_compare_dirs(id, compare_data.left, compare_data.right, compare_data.hash)
+            .await
+        {
+            Ok(comparison) => Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(serde_json::to_string(&comparison).unwrap()))
+                .unwrap(),
+            Err(error) => ErrorMessage::new(
+                "cannot compare the directories",
+                StatusCode::SERVICE_UNAVAILABLE,
+            )
+            .error_force(error)
+            .to_response(),
+        }
+    }
+}
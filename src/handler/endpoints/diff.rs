@@ -0,0 +1,77 @@
+use super::*;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::ErrorMessage;
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+fn default_context() -> usize {
+    3
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DiffData {
+    pub left: String,
+    pub right: String,
+    #[serde(default)]
+    pub ignore_whitespace: bool,
+    #[serde(default = "default_context")]
+    pub context: usize,
+}
+
+/// API endpoint which compares two text files on the sftp server
+/// and returns the unified diff with the list of hunks
+pub struct DiffEndpoint;
+
+#[async_trait]
+impl Endpoint for DiffEndpoint {
+    fn method(&self) -> Option<Method> {
+        Some(Method::POST)
+    }
+
+    fn path(&self) -> &'static str {
+        "diff"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let diff_data = match parsers::body_json::<DiffData>(body).await {
+            Ok(diff_data) => diff_data,
+            Err(error) => return error.to_response(),
+        };
+
+        match Cache::ftp_diff_files(
+            id,
+            diff_data.left,
+            diff_data.right,
+            diff_data.ignore_whitespace,
+            diff_data.context,
+        )
+        .await
+        {
+            Ok(diff) => Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(serde_json::to_string(&diff).unwrap()))
+                .unwrap(),
+            Err(error) => {
+                ErrorMessage::new("cannot compare the files", StatusCode::SERVICE_UNAVAILABLE)
+                    .error_force(error)
+                    .to_response()
+            }
+        }
+    }
+}
@@ -1,25 +1,28 @@
 mod listing;
+mod upload;
 
 use super::*;
-use crate::cache::{Cache, FtpClientID};
+use crate::cache::ftp::{self, ChunkSize, RangeSpec, StreamRequest};
+use crate::cache::Cache;
 
-use crate::handler::responses;
+use crate::handler::compression::Encoding;
 use crate::handler::responses::ErrorMessage;
+use crate::handler::{parsers, responses};
 use crate::utils;
-use cookie::Cookie;
-use hyper::header::COOKIE;
+use hyper::header::{CACHE_CONTROL, IF_RANGE, PRAGMA, RANGE};
 use hyper::http::response::Builder;
 use hyper::StatusCode;
 use listing::*;
 use ssh2::{FileStat, FileType};
 
-use std::path::Path;
 use crate::utils::BoolOptional;
+use std::path::Path;
 
 /// API endpoint for communicating with the sftp client
 /// * List directory entities
 /// * Download files
-/// * Uploading files (wip)
+/// * Cancel file transfers
+/// * Upload files with the tus protocol
 /// * Creating new directories (wip)
 pub struct FTPEndpoint;
 
@@ -37,35 +40,33 @@ impl Endpoint for FTPEndpoint {
         EndpointClassification::API(1)
     }
 
-    async fn call(&self, meta: Parts, _body: Body, _address: SocketAddr) -> Response<Body> {
-        // get the ftp client id
-        let mut id = String::new();
-        if let Some(ftp) = meta.headers.get("ftp") {
-            id = ftp.to_str().unwrap_or("").to_string();
-        } else if let Some(cookie) = meta.headers.get(COOKIE) {
-            if let Ok(cookie) = Cookie::parse(cookie.to_str().unwrap_or("")) {
-                id = cookie.value().to_string();
-            }
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        // tus discovery doesn't require the client
+        if meta.method == Method::OPTIONS {
+            return upload::options();
+        }
+
+        if let Some(response) = upload::check_version(&meta) {
+            return response;
         }
 
-        if id.is_empty() {
+        // get the ftp client id
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
             return ErrorMessage::new(
                 "no ftp client identification provided",
                 StatusCode::UNAUTHORIZED,
             )
             .to_response();
-        }
-        let id = FtpClientID::new(id);
+        };
 
         // resolve the ftp path
-        let mut ftp_path = urlencoding::decode(meta.uri.path().to_string().as_str())
-            .unwrap_or_default()
-            .to_string();
-        if let Some(i) = ftp_path.find("/ftp") {
-            ftp_path.replace_range(..i + 4, "");
+        let ftp_path = if let Some(ftp_path) = parsers::expanded_path(&meta, self.path()) {
+            ftp_path
         } else {
             return ErrorMessage::new("invalid uri path", StatusCode::BAD_REQUEST).to_response();
-        }
+        };
         let ftp_path = Path::new(&ftp_path);
 
         // helpful when the dir name contains a dot
@@ -89,11 +90,30 @@ impl Endpoint for FTPEndpoint {
             .map(|as_html| as_html.to_str().unwrap_or_default() == "true")
             .into();
 
+        // the query parameter allows to use the plain links for downloading
+        let stream: utils::BoolOptional = meta
+            .headers
+            .get("stream")
+            .and_then(|stream| stream.to_str().ok().map(str::to_string))
+            .or_else(|| parsers::query_param(&meta, "stream"))
+            .map(|stream| stream == "true")
+            .into();
+
+        // inline files are opened directly in the browser tab
+        let inline = meta
+            .headers
+            .get("disposition")
+            .and_then(|disposition| disposition.to_str().ok().map(str::to_string))
+            .or_else(|| parsers::query_param(&meta, "disposition"))
+            .map(|disposition| disposition == "inline")
+            .unwrap_or(false);
+
         match &meta.method {
             &Method::GET => {
                 // list all files in the directory
                 if is_dir {
-                    match Cache::ftp_read_dir(id, ftp_path.to_string_lossy()).await {
+                    match Cache::ftp_read_dir(id, ftp_path.to_string_lossy(), no_cache(&meta)).await
+                    {
                         Ok(vec) => {
                             let mut listings: Vec<Listing> =
                                 vec.into_iter().map(Listing::from).collect();
@@ -105,10 +125,11 @@ impl Endpoint for FTPEndpoint {
                                 serde_json::to_string(&listings).unwrap()
                             };
 
-                            return Builder::new()
-                                .status(StatusCode::OK)
-                                .body(Body::from(data))
-                                .unwrap();
+                            responses::listing(
+                                data,
+                                Encoding::negotiate(&meta),
+                                &parsers::conditions(&meta),
+                            )
                         }
                         Err(e) => ErrorMessage::new(
                             "cannot list the directory",
@@ -118,9 +139,69 @@ impl Endpoint for FTPEndpoint {
                         .to_response(),
                     }
                 }
+                // stream the entire file or the requested range in a single response
+                else if stream.is_true() || inline || meta.headers.contains_key(RANGE) {
+                    let request = StreamRequest {
+                        range: meta
+                            .headers
+                            .get(RANGE)
+                            .and_then(|range| RangeSpec::parse(range.to_str().unwrap_or_default())),
+                        if_range: meta
+                            .headers
+                            .get(IF_RANGE)
+                            .map(|if_range| if_range.to_str().unwrap_or_default().to_string()),
+                        conditions: parsers::conditions(&meta),
+                    };
+
+                    match Cache::ftp_stream_file(id, ftp_path.to_string_lossy(), request).await {
+                        Ok(file_stream) => responses::file_stream(
+                            file_stream,
+                            ftp_path,
+                            Encoding::negotiate(&meta),
+                            inline,
+                        ),
+                        Err(error) => ErrorMessage::new(
+                            "cannot download the file",
+                            StatusCode::SERVICE_UNAVAILABLE,
+                        )
+                        .error_force(error)
+                        .to_response(),
+                    }
+                }
                 // download the file
                 else {
-                    match Cache::ftp_read_file(id, ftp_path.to_string_lossy()).await {
+                    let chunk_size = meta.headers.get("pack-chunk-size").and_then(|chunk_size| {
+                        ChunkSize::parse(chunk_size.to_str().unwrap_or_default())
+                    });
+                    let pack = meta
+                        .headers
+                        .get("pack-number")
+                        .and_then(|pack| pack.to_str().unwrap_or_default().parse().ok());
+
+                    // the conditions are checked only before the new transfer starts
+                    let conditions = parsers::conditions(&meta);
+                    if transfer_token(&meta).is_none() && pack.is_none() && !conditions.is_empty() {
+                        if let Ok(stat) =
+                            Cache::ftp_open_stat(id.clone(), ftp_path.to_string_lossy()).await
+                        {
+                            let (size, modified) =
+                                (stat.size.unwrap_or(0), stat.mtime.unwrap_or(0) as i64);
+                            let etag = ftp::file_etag(&ftp_path.to_string_lossy(), size, modified);
+                            if conditions.not_modified(&etag, Some(modified)) {
+                                return responses::not_modified(etag, modified);
+                            }
+                        }
+                    }
+
+                    match Cache::ftp_read_file(
+                        id,
+                        ftp_path.to_string_lossy(),
+                        transfer_token(&meta),
+                        chunk_size,
+                        pack,
+                    )
+                    .await
+                    {
                         Ok(content_pack) => {
                             return responses::file_content_pack(content_pack, ftp_path);
                         }
@@ -137,12 +218,45 @@ impl Endpoint for FTPEndpoint {
             }
 
             &Method::POST => {
-                // upload file
-                return ErrorMessage::new(
-                    "uploading files not implemented",
-                    StatusCode::NOT_IMPLEMENTED,
-                )
-                .to_response();
+                // create a resumable upload of the file
+                return upload::create(id, &meta, ftp_path).await;
+            }
+
+            &Method::HEAD => {
+                // get the offset of the upload
+                return match parsers::query_param(&meta, "upload") {
+                    Some(token) => upload::head(id, token).await,
+                    None => ErrorMessage::new("no upload token provided", StatusCode::BAD_REQUEST)
+                        .to_response(),
+                };
+            }
+
+            &Method::DELETE => {
+                // cancel the file transfer
+                let token = if let Some(token) = transfer_token(&meta) {
+                    token
+                } else {
+                    return ErrorMessage::new(
+                        "no transfer token provided",
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .to_response();
+                };
+
+                if Cache::cancel_transfer(id, token).await {
+                    Builder::new()
+                        .status(StatusCode::OK)
+                        .body(Body::from("true"))
+                        .unwrap()
+                } else {
+                    ErrorMessage::new("transfer not found", StatusCode::NOT_FOUND).to_response()
+                }
+            }
+
+            &Method::PATCH if parsers::query_param(&meta, "upload").is_some() => {
+                // continue the resumable upload
+                let token = parsers::query_param(&meta, "upload").unwrap();
+                return upload::patch(id, token, &meta, body).await;
             }
 
             &Method::PATCH => {
@@ -160,3 +274,23 @@ impl Endpoint for FTPEndpoint {
         }
     }
 }
+
+/// Token of the continued file transfer from the "pack-transfer" header
+fn transfer_token(meta: &Parts) -> Option<String> {
+    meta.headers
+        .get("pack-transfer")
+        .and_then(|token| token.to_str().ok())
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+/// Checks if the client requested the fresh listing with the "Cache-Control: no-cache" header
+fn no_cache(meta: &Parts) -> bool {
+    meta.headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .chain(meta.headers.get_all(PRAGMA).iter())
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| matches!(directive.trim(), "no-cache" | "no-store"))
+}
@@ -0,0 +1,264 @@
+use super::*;
+use crate::cache::ftp::UploadInfo;
+use crate::cache::FtpClientID;
+use crate::CONFIG;
+
+use hyper::body::HttpBody;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use tokio::sync::mpsc;
+
+/// The only supported version of the tus protocol
+const TUS_VERSION: &str = "1.0.0";
+
+/// Content type required in the PATCH requests
+const UPLOAD_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Response builder with the headers required in every tus response
+fn tus_builder(status: StatusCode) -> Builder {
+    Builder::new()
+        .status(status)
+        .header("Tus-Resumable", TUS_VERSION)
+}
+
+fn tus_error(error: ErrorMessage) -> Response<Body> {
+    let mut response = error.to_response();
+    response
+        .headers_mut()
+        .insert("Tus-Resumable", TUS_VERSION.parse().unwrap());
+    response
+}
+
+fn header_u64(meta: &Parts, name: &str) -> Option<u64> {
+    meta.headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Returns the error response if the client uses unsupported version of the protocol
+pub fn check_version(meta: &Parts) -> Option<Response<Body>> {
+    match meta.headers.get("Tus-Resumable") {
+        Some(version) if version != TUS_VERSION => Some(
+            tus_builder(StatusCode::PRECONDITION_FAILED)
+                .header("Tus-Version", TUS_VERSION)
+                .body(Body::empty())
+                .unwrap(),
+        ),
+        _ => None,
+    }
+}
+
+/// Describes the supported protocol version and extensions
+pub fn options() -> Response<Body> {
+    tus_builder(StatusCode::NO_CONTENT)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", "creation")
+        .header("Tus-Max-Size", CONFIG.upload_max_size)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Creates a new upload of the file (creation extension)
+pub async fn create(id: FtpClientID, meta: &Parts, ftp_path: &Path) -> Response<Body> {
+    let length = if let Some(length) = header_u64(meta, "Upload-Length") {
+        length
+    } else {
+        return tus_error(ErrorMessage::new(
+            "invalid or missing Upload-Length header",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    if length > CONFIG.upload_max_size {
+        return tus_error(ErrorMessage::new(
+            "file exceeds the size limit",
+            StatusCode::PAYLOAD_TOO_LARGE,
+        ));
+    }
+
+    let file = ftp_path.to_string_lossy().to_string();
+    let token = match Cache::upload_create(id.clone(), &file, length).await {
+        Ok(token) => token,
+        Err(error) => {
+            return tus_error(
+                ErrorMessage::new("cannot create the upload", StatusCode::SERVICE_UNAVAILABLE)
+                    .error_force(error),
+            )
+        }
+    };
+
+    // empty files don't receive any PATCH requests
+    if length == 0 {
+        if let Some(upload_info) = Cache::upload_get(id.clone(), &token).await {
+            if let Err(error) =
+                Cache::ftp_finish_upload(id.clone(), upload_info.temp_path, &file).await
+            {
+                return tus_error(
+                    ErrorMessage::new("cannot create the file", StatusCode::SERVICE_UNAVAILABLE)
+                        .error_force(error),
+                );
+            }
+        }
+        Cache::upload_remove(id, &token).await;
+    }
+
+    tus_builder(StatusCode::CREATED)
+        .header(
+            LOCATION,
+            format!("{}{}?upload={token}", CONFIG.host, meta.uri.path()),
+        )
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Returns the current offset of the upload
+pub async fn head(id: FtpClientID, token: String) -> Response<Body> {
+    match Cache::upload_get(id, token).await {
+        Some(upload_info) => tus_builder(StatusCode::OK)
+            .header("Upload-Offset", upload_info.offset)
+            .header("Upload-Length", upload_info.length)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::empty())
+            .unwrap(),
+        None => tus_builder(StatusCode::NOT_FOUND)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::empty())
+            .unwrap(),
+    }
+}
+
+/// Writes the request body into the upload at the offset,
+/// the file is moved into its destination once all the bytes are received
+pub async fn patch(id: FtpClientID, token: String, meta: &Parts, body: Body) -> Response<Body> {
+    let content_type = meta
+        .headers
+        .get(CONTENT_TYPE)
+        .map(|content_type| content_type.to_str().unwrap_or_default());
+    if content_type != Some(UPLOAD_CONTENT_TYPE) {
+        return tus_error(ErrorMessage::new(
+            "invalid content type",
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        ));
+    }
+
+    let offset = if let Some(offset) = header_u64(meta, "Upload-Offset") {
+        offset
+    } else {
+        return tus_error(ErrorMessage::new(
+            "invalid or missing Upload-Offset header",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+
+    let upload_info = match Cache::upload_acquire(id.clone(), &token).await {
+        Some(upload_info) if upload_info.busy => {
+            return tus_error(ErrorMessage::new(
+                "upload is already in progress",
+                StatusCode::LOCKED,
+            ))
+        }
+        Some(upload_info) => upload_info,
+        None => return tus_error(ErrorMessage::new("upload not found", StatusCode::NOT_FOUND)),
+    };
+
+    if offset != upload_info.offset {
+        Cache::upload_release(id, &token, upload_info.offset).await;
+        return tus_error(ErrorMessage::new(
+            "offset does not match the upload",
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    // the upload is released in the separate task,
+    // so it doesn't stay busy when the connection gets dropped
+    tokio::spawn(write(id, token, upload_info, body))
+        .await
+        .unwrap_or_else(|_| {
+            tus_error(ErrorMessage::new(
+                "cannot write the upload",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))
+        })
+}
+
+/// Writes the body into the acquired upload and releases it
+async fn write(
+    id: FtpClientID,
+    token: String,
+    mut upload_info: UploadInfo,
+    mut body: Body,
+) -> Response<Body> {
+    let offset = upload_info.offset;
+
+    // the chunks are written while the body is being received
+    let (tx, rx) = mpsc::channel(8);
+    let writer = tokio::spawn(Cache::ftp_write_upload(
+        id.clone(),
+        upload_info.temp_path.clone(),
+        offset,
+        rx,
+    ));
+
+    let mut left = upload_info.length - offset;
+    let mut too_long = false;
+    while let Some(Ok(chunk)) = body.data().await {
+        if chunk.len() as u64 > left {
+            too_long = true;
+            break;
+        }
+        left -= chunk.len() as u64;
+
+        if tx.send(chunk.to_vec()).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+
+    let new_offset = match writer.await {
+        Ok(Ok(new_offset)) => new_offset,
+        Ok(Err(error)) => {
+            Cache::upload_release(id, &token, offset).await;
+            return tus_error(
+                ErrorMessage::new("cannot write the upload", StatusCode::SERVICE_UNAVAILABLE)
+                    .error_force(error),
+            );
+        }
+        Err(_) => {
+            Cache::upload_release(id, &token, offset).await;
+            return tus_error(ErrorMessage::new(
+                "cannot write the upload",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+    };
+    upload_info.offset = new_offset;
+
+    if too_long {
+        Cache::upload_release(id, &token, new_offset).await;
+        return tus_error(ErrorMessage::new(
+            "body exceeds the Upload-Length",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    // the upload stays busy until it's moved, so it cannot be finished twice
+    if upload_info.is_complete() {
+        if let Err(error) =
+            Cache::ftp_finish_upload(id.clone(), upload_info.temp_path, upload_info.path).await
+        {
+            Cache::upload_release(id, &token, new_offset).await;
+            return tus_error(
+                ErrorMessage::new("cannot finish the upload", StatusCode::SERVICE_UNAVAILABLE)
+                    .error_force(error),
+            );
+        }
+        Cache::upload_remove(id, &token).await;
+    } else {
+        Cache::upload_release(id, &token, new_offset).await;
+    }
+
+    tus_builder(StatusCode::NO_CONTENT)
+        .header("Upload-Offset", new_offset)
+        .body(Body::empty())
+        .unwrap()
+}
@@ -1,16 +1,34 @@
+pub mod batch;
+pub mod compare;
+pub mod diff;
 pub mod ftp;
 pub mod index;
 pub mod login;
 pub mod main;
+pub mod share;
+pub mod terminal;
+pub mod transfers;
+pub mod trash;
+pub mod versions;
+pub mod watch;
 
 pub use index::*;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::net::SocketAddr;
 
+use crate::handler::endpoints::batch::BatchEndpoint;
+use crate::handler::endpoints::compare::CompareEndpoint;
+use crate::handler::endpoints::diff::DiffEndpoint;
 use crate::handler::endpoints::ftp::FTPEndpoint;
 use crate::handler::endpoints::login::LoginEndpoint;
 use crate::handler::endpoints::main::MainEndpoint;
+use crate::handler::endpoints::share::{ShareEndpoint, SharedFileEndpoint};
+use crate::handler::endpoints::terminal::TerminalEndpoint;
+use crate::handler::endpoints::transfers::TransfersEndpoint;
+use crate::handler::endpoints::trash::TrashEndpoint;
+use crate::handler::endpoints::versions::VersionsEndpoint;
+use crate::handler::endpoints::watch::WatchEndpoint;
 use hyper::http::request::Parts;
 use hyper::{Body, Method, Response};
 use std::path::PathBuf;
@@ -28,7 +46,17 @@ lazy_static! {
             .add(IndexEndpoint)
             .add(MainEndpoint)
             .add(LoginEndpoint)
-            .add(FTPEndpoint);
+            .add(FTPEndpoint)
+            .add(DiffEndpoint)
+            .add(CompareEndpoint)
+            .add(BatchEndpoint)
+            .add(TrashEndpoint)
+            .add(VersionsEndpoint)
+            .add(TerminalEndpoint)
+            .add(ShareEndpoint)
+            .add(SharedFileEndpoint)
+            .add(WatchEndpoint)
+            .add(TransfersEndpoint);
 
         #[cfg(debug_assertions)]
         {
@@ -0,0 +1,161 @@
+use super::*;
+use crate::cache::{share, Cache};
+use crate::handler::parsers::{self, extension_to_mime};
+use crate::handler::responses::{self, ErrorMessage};
+use crate::utils::time;
+
+use hyper::header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShareData {
+    pub path: String,
+    pub lifetime_s: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevokeData {
+    pub id: String,
+}
+
+/// API endpoint for managing the user's share links
+/// * List active links (GET)
+/// * Create a link (POST)
+/// * Revoke a link (DELETE)
+pub struct ShareEndpoint;
+
+#[async_trait]
+impl Endpoint for ShareEndpoint {
+    fn path(&self) -> &'static str {
+        "share"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let json = |data: String| {
+            Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(data))
+                .unwrap()
+        };
+
+        match meta.method {
+            Method::GET => json(serde_json::to_string(&Cache::share_list(id).await).unwrap()),
+
+            Method::POST => {
+                let share_data = match parsers::body_json::<ShareData>(body).await {
+                    Ok(share_data) => share_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                match Cache::share_create(id, share_data.path, share_data.lifetime_s).await {
+                    Ok(link) => json(serde_json::to_string(&link).unwrap()),
+                    Err(error) => {
+                        ErrorMessage::new("cannot share the file", StatusCode::SERVICE_UNAVAILABLE)
+                            .error_force(error)
+                            .to_response()
+                    }
+                }
+            }
+
+            Method::DELETE => {
+                let revoke_data = match parsers::body_json::<RevokeData>(body).await {
+                    Ok(revoke_data) => revoke_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                if Cache::share_revoke(id, revoke_data.id).await {
+                    json("true".to_string())
+                } else {
+                    ErrorMessage::new("share link not found", StatusCode::NOT_FOUND).to_response()
+                }
+            }
+
+            _ => responses::e404(),
+        }
+    }
+}
+
+/// Public endpoint which serves the shared file snapshot,
+/// does not require the sftp client
+pub struct SharedFileEndpoint;
+
+#[async_trait]
+impl Endpoint for SharedFileEndpoint {
+    fn uri_path_expanded(&self) -> bool {
+        true
+    }
+
+    fn method(&self) -> Option<Method> {
+        Some(Method::GET)
+    }
+
+    fn path(&self) -> &'static str {
+        "share"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::BIN
+    }
+
+    async fn call(&self, meta: Parts, _body: Body, _address: SocketAddr) -> Response<Body> {
+        let share_id = parsers::expanded_path(&meta, self.path())
+            .map(|path| path.trim_start_matches('/').to_string())
+            .unwrap_or_default();
+        let expires_at = parsers::query_param(&meta, "expires")
+            .and_then(|expires| expires.parse::<i64>().ok())
+            .unwrap_or(0);
+        let signature = parsers::query_param(&meta, "signature").unwrap_or_default();
+
+        if !share::verify(&share_id, expires_at, &signature) {
+            return ErrorMessage::new("invalid share link", StatusCode::FORBIDDEN).to_response();
+        }
+
+        let link = match Cache::share_get(&share_id).await {
+            Some(link) if link.expires_at == expires_at && expires_at > time::now() => link,
+            _ => return ErrorMessage::new("share link expired", StatusCode::GONE).to_response(),
+        };
+
+        let file = match tokio::fs::File::open(share::data_path(&link.id)).await {
+            Ok(file) => file,
+            Err(error) => {
+                return ErrorMessage::new("cannot read the shared file", StatusCode::GONE)
+                    .error(error)
+                    .to_response()
+            }
+        };
+
+        let mime = extension_to_mime(
+            Path::new(&link.name)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+        );
+
+        Builder::new()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime)
+            .header(CONTENT_LENGTH, link.size)
+            .header(
+                CONTENT_DISPOSITION,
+                responses::content_disposition("attachment", &link.name),
+            )
+            .body(responses::local_file_body(file))
+            .unwrap()
+    }
+}
@@ -0,0 +1,186 @@
+use super::*;
+use crate::cache::ftp::{self, TerminalInput};
+use crate::cache::Cache;
+use crate::handler::endpoints::login::LoginData;
+use crate::handler::parsers;
+use crate::handler::responses::ErrorMessage;
+use crate::logging::*;
+use crate::CONFIG;
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::header::{CONNECTION, ORIGIN, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE};
+use hyper::http::response::Builder;
+use hyper::upgrade::Upgraded;
+use hyper::{Request, StatusCode};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Control messages sent by the client in the text frames,
+/// binary frames are passed directly into the terminal
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TerminalMessage {
+    Input { data: String },
+    Resize { cols: u32, rows: u32 },
+    Error { message: String },
+}
+
+/// API endpoint which opens the ssh shell and relays it over the WebSocket
+pub struct TerminalEndpoint;
+
+#[async_trait]
+impl Endpoint for TerminalEndpoint {
+    fn method(&self) -> Option<Method> {
+        Some(Method::GET)
+    }
+
+    fn path(&self) -> &'static str {
+        "terminal"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        if !CONFIG.terminal_enabled {
+            return ErrorMessage::new("terminal is disabled", StatusCode::FORBIDDEN).to_response();
+        }
+
+        // the browsers send the cookie with the cross-site WebSocket handshakes as well
+        if !is_same_origin(&meta) {
+            return ErrorMessage::new("origin is not allowed", StatusCode::FORBIDDEN).to_response();
+        }
+
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let is_websocket = meta
+            .headers
+            .get(UPGRADE)
+            .map(|upgrade| upgrade.as_bytes().eq_ignore_ascii_case(b"websocket"))
+            .unwrap_or(false);
+        let key = match meta.headers.get(SEC_WEBSOCKET_KEY) {
+            Some(key) if is_websocket => key.clone(),
+            _ => {
+                return ErrorMessage::new("websocket upgrade required", StatusCode::BAD_REQUEST)
+                    .to_response()
+            }
+        };
+
+        let login_data = if let Some(login_data) = Cache::ftp_credentials(id).await {
+            login_data
+        } else {
+            return ErrorMessage::new("invalid ftp client id", StatusCode::UNAUTHORIZED)
+                .to_response();
+        };
+
+        let dimension = |name: &str, default: u32| {
+            parsers::query_param(&meta, name)
+                .and_then(|value| value.parse::<u32>().ok())
+                .unwrap_or(default)
+        };
+        let cols = dimension("cols", 80);
+        let rows = dimension("rows", 24);
+
+        let mut request = Request::from_parts(meta, body);
+        tokio::spawn(async move {
+            match hyper::upgrade::on(&mut request).await {
+                Ok(upgraded) => {
+                    let websocket =
+                        WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                    relay(websocket, login_data, cols, rows).await;
+                }
+                Err(error) => warn!("cannot upgrade the terminal connection because {error}"),
+            }
+        });
+
+        Builder::new()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(CONNECTION, "upgrade")
+            .header(UPGRADE, "websocket")
+            .header(SEC_WEBSOCKET_ACCEPT, derive_accept_key(key.as_bytes()))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+/// Checks if the "Origin" header matches the configured host, the missing header is rejected
+fn is_same_origin(meta: &Parts) -> bool {
+    meta.headers
+        .get(ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(|origin| {
+            origin
+                .trim_end_matches('/')
+                .eq_ignore_ascii_case(CONFIG.host.trim_end_matches('/'))
+        })
+        .unwrap_or(false)
+}
+
+/// Relays the WebSocket frames between the client and the terminal session
+async fn relay(websocket: WebSocketStream<Upgraded>, login_data: LoginData, cols: u32, rows: u32) {
+    let (mut ws_tx, mut ws_rx) = websocket.split();
+    let (input_tx, input_rx) = mpsc::channel(64);
+    let (output_tx, mut output_rx) = mpsc::channel(64);
+
+    let terminal = tokio::task::spawn_blocking(move || {
+        ftp::run_terminal(login_data, cols, rows, input_rx, output_tx)
+    });
+
+    let reader = async move {
+        while let Some(Ok(message)) = ws_rx.next().await {
+            let input = match message {
+                Message::Binary(data) => TerminalInput::Data(data),
+                Message::Text(text) => match serde_json::from_str::<TerminalMessage>(&text) {
+                    Ok(TerminalMessage::Input { data }) => TerminalInput::Data(data.into_bytes()),
+                    Ok(TerminalMessage::Resize { cols, rows }) => {
+                        TerminalInput::Resize { cols, rows }
+                    }
+                    _ => continue,
+                },
+                Message::Close(_) => break,
+                _ => continue,
+            };
+
+            if input_tx.send(input).await.is_err() {
+                break;
+            }
+        }
+    };
+
+    let writer = async move {
+        while let Some(bytes) = output_rx.recv().await {
+            if ws_tx.send(Message::Binary(bytes)).await.is_err() {
+                return;
+            }
+        }
+
+        if let Ok(Err(error)) = terminal.await {
+            let message = TerminalMessage::Error {
+                message: error.to_string(),
+            };
+            ws_tx
+                .send(Message::Text(serde_json::to_string(&message).unwrap()))
+                .await
+                .ok();
+        }
+        ws_tx.send(Message::Close(None)).await.ok();
+    };
+
+    // dropping the input sender closes the terminal session
+    tokio::select! {
+        _ = reader => {}
+        _ = writer => {}
+    }
+}
@@ -0,0 +1,66 @@
+use super::*;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::{self, ErrorMessage};
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CancelData {
+    pub transfer: String,
+}
+
+/// API endpoint for managing the user's file transfers
+/// * List active transfers (GET)
+/// * Cancel a transfer (DELETE)
+pub struct TransfersEndpoint;
+
+#[async_trait]
+impl Endpoint for TransfersEndpoint {
+    fn path(&self) -> &'static str {
+        "transfers"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let json = |data: String| {
+            Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(data))
+                .unwrap()
+        };
+
+        match meta.method {
+            Method::GET => json(serde_json::to_string(&Cache::list_transfers(id).await).unwrap()),
+
+            Method::DELETE => {
+                let cancel_data = match parsers::body_json::<CancelData>(body).await {
+                    Ok(cancel_data) => cancel_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                if Cache::cancel_transfer(id, cancel_data.transfer).await {
+                    json("true".to_string())
+                } else {
+                    ErrorMessage::new("transfer not found", StatusCode::NOT_FOUND).to_response()
+                }
+            }
+
+            _ => responses::e404(),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use super::*;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::{self, ErrorMessage};
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestoreData {
+    pub id: String,
+    pub to: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PurgeData {
+    pub id: Option<String>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+/// API endpoint for managing the user's trash
+/// * List trashed entities (GET)
+/// * Restore an entity (POST)
+/// * Purge an entity or the whole trash (DELETE)
+pub struct TrashEndpoint;
+
+#[async_trait]
+impl Endpoint for TrashEndpoint {
+    fn path(&self) -> &'static str {
+        "trash"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let json = |data: String| {
+            Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(data))
+                .unwrap()
+        };
+
+        match meta.method {
+            Method::GET => match Cache::ftp_list_trash(id).await {
+                Ok(entries) => json(serde_json::to_string(&entries).unwrap()),
+                Err(error) => {
+                    ErrorMessage::new("cannot list the trash", StatusCode::SERVICE_UNAVAILABLE)
+                        .error_force(error)
+                        .to_response()
+                }
+            },
+
+            Method::POST => {
+                let restore_data = match parsers::body_json::<RestoreData>(body).await {
+                    Ok(restore_data) => restore_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                match Cache::ftp_restore_trash(id, restore_data.id, restore_data.to).await {
+                    Ok(entry) => json(serde_json::to_string(&entry).unwrap()),
+                    Err(error) => ErrorMessage::new(
+                        "cannot restore the entity",
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .error_force(error)
+                    .to_response(),
+                }
+            }
+
+            Method::DELETE => {
+                let purge_data = match parsers::body_json::<PurgeData>(body).await {
+                    Ok(purge_data) => purge_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                if purge_data.id.is_none() && !purge_data.all {
+                    return ErrorMessage::new(
+                        "no trash id provided, set \"all\" to purge the whole trash",
+                        StatusCode::BAD_REQUEST,
+                    )
+                    .to_response();
+                }
+
+                match Cache::ftp_purge_trash(id, purge_data.id).await {
+                    Ok(purged) => json(purged.to_string()),
+                    Err(error) => {
+                        ErrorMessage::new("cannot purge the trash", StatusCode::SERVICE_UNAVAILABLE)
+                            .error_force(error)
+                            .to_response()
+                    }
+                }
+            }
+
+            _ => responses::e404(),
+        }
+    }
+}
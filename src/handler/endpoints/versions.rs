@@ -0,0 +1,88 @@
+use super::*;
+use crate::cache::Cache;
+use crate::handler::parsers;
+use crate::handler::responses::{self, ErrorMessage};
+
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestoreVersionData {
+    pub version: String,
+}
+
+/// API endpoint for managing previous versions of the file
+/// * List versions (GET)
+/// * Restore a version (POST)
+pub struct VersionsEndpoint;
+
+#[async_trait]
+impl Endpoint for VersionsEndpoint {
+    fn uri_path_expanded(&self) -> bool {
+        true
+    }
+
+    fn path(&self) -> &'static str {
+        "versions"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let ftp_path = match parsers::expanded_path(&meta, self.path()) {
+            Some(ftp_path) if !ftp_path.is_empty() => ftp_path,
+            _ => {
+                return ErrorMessage::new("invalid uri path", StatusCode::BAD_REQUEST).to_response()
+            }
+        };
+
+        let json = |data: String| {
+            Builder::new()
+                .status(StatusCode::OK)
+                .body(Body::from(data))
+                .unwrap()
+        };
+
+        match meta.method {
+            Method::GET => match Cache::ftp_list_versions(id, ftp_path).await {
+                Ok(versions) => json(serde_json::to_string(&versions).unwrap()),
+                Err(error) => {
+                    ErrorMessage::new("cannot list the versions", StatusCode::SERVICE_UNAVAILABLE)
+                        .error_force(error)
+                        .to_response()
+                }
+            },
+
+            Method::POST => {
+                let restore_data = match parsers::body_json::<RestoreVersionData>(body).await {
+                    Ok(restore_data) => restore_data,
+                    Err(error) => return error.to_response(),
+                };
+
+                match Cache::ftp_restore_version(id, ftp_path, restore_data.version).await {
+                    Ok(version) => json(serde_json::to_string(&version).unwrap()),
+                    Err(error) => ErrorMessage::new(
+                        "cannot restore the version",
+                        StatusCode::SERVICE_UNAVAILABLE,
+                    )
+                    .error_force(error)
+                    .to_response(),
+                }
+            }
+
+            _ => responses::e404(),
+        }
+    }
+}
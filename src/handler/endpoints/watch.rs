@@ -0,0 +1,175 @@
+use super::*;
+use crate::cache::{Cache, FtpClientID};
+use crate::handler::parsers;
+use crate::handler::responses::ErrorMessage;
+use crate::CONFIG;
+
+use hyper::body::{Bytes, Sender};
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+use ssh2::FileStat;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// State of the directory entity which is compared between the snapshots
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl From<(PathBuf, FileStat)> for WatchEntry {
+    fn from((path, stat): (PathBuf, FileStat)) -> Self {
+        Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            is_dir: stat.is_dir(),
+            size: stat.size.unwrap_or(0),
+            modified: stat.mtime.unwrap_or(0),
+        }
+    }
+}
+
+type Snapshot = BTreeMap<String, WatchEntry>;
+
+/// API endpoint which streams the changes of the directory as Server-Sent Events
+pub struct WatchEndpoint;
+
+#[async_trait]
+impl Endpoint for WatchEndpoint {
+    fn uri_path_expanded(&self) -> bool {
+        true
+    }
+
+    fn method(&self) -> Option<Method> {
+        Some(Method::GET)
+    }
+
+    fn path(&self) -> &'static str {
+        "watch"
+    }
+
+    fn classification(&self) -> EndpointClassification {
+        EndpointClassification::API(1)
+    }
+
+    async fn call(&self, meta: Parts, _body: Body, _address: SocketAddr) -> Response<Body> {
+        let id = if let Some(id) = parsers::ftp_client_id(&meta) {
+            id
+        } else {
+            return ErrorMessage::new(
+                "no ftp client identification provided",
+                StatusCode::UNAUTHORIZED,
+            )
+            .to_response();
+        };
+
+        let ftp_path = match parsers::expanded_path(&meta, self.path()) {
+            Some(ftp_path) if !ftp_path.is_empty() => ftp_path,
+            _ => "/".to_string(),
+        };
+
+        // the first snapshot is taken before the stream starts,
+        // so the invalid directory is reported with the proper status code
+        let snapshot = match read_snapshot(id.clone(), &ftp_path).await {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                return ErrorMessage::new(
+                    "cannot read the directory",
+                    StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .error_force(error)
+                .to_response()
+            }
+        };
+
+        let (sender, body) = Body::channel();
+        tokio::spawn(watch(id, ftp_path, snapshot, sender));
+
+        Builder::new()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(body)
+            .unwrap()
+    }
+}
+
+async fn read_snapshot(id: FtpClientID, dir: &String) -> anyhow::Result<Snapshot> {
+    // the changes have to be detected as soon as possible
+    Ok(Cache::ftp_read_dir(id, dir, true)
+        .await?
+        .into_iter()
+        .map(WatchEntry::from)
+        .map(|entry| (entry.name.clone(), entry))
+        .collect())
+}
+
+/// Formats a single Server-Sent Event
+fn event(name: &str, data: &impl serde::Serialize) -> String {
+    format!(
+        "event: {name}\ndata: {}\n\n",
+        serde_json::to_string(data).unwrap()
+    )
+}
+
+/// Compares two snapshots and returns the events describing the changes
+fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> String {
+    let mut events = String::new();
+
+    for (name, entry) in new {
+        match old.get(name) {
+            None => events.push_str(&event("added", entry)),
+            Some(old_entry) if old_entry != entry => events.push_str(&event("changed", entry)),
+            _ => {}
+        }
+    }
+
+    for (name, entry) in old {
+        if !new.contains_key(name) {
+            events.push_str(&event("removed", entry));
+        }
+    }
+
+    events
+}
+
+/// Polls the directory until the client disconnects or the sftp client stops working
+async fn watch(id: FtpClientID, dir: String, mut snapshot: Snapshot, mut sender: Sender) {
+    let interval = Duration::from_secs(CONFIG.watch_interval_s.max(1));
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let new_snapshot = match read_snapshot(id.clone(), &dir).await {
+            Ok(new_snapshot) => new_snapshot,
+            Err(error) => {
+                let message =
+                    ErrorMessage::new("cannot read the directory", StatusCode::SERVICE_UNAVAILABLE)
+                        .error_force(error);
+                sender
+                    .send_data(Bytes::from(event("error", &message)))
+                    .await
+                    .ok();
+                return;
+            }
+        };
+
+        let mut events = diff_snapshots(&snapshot, &new_snapshot);
+        snapshot = new_snapshot;
+
+        // the comment is sent when nothing has changed to detect the closed connection
+        if events.is_empty() {
+            events.push_str(": keep-alive\n\n");
+        }
+
+        if sender.send_data(Bytes::from(events)).await.is_err() {
+            return;
+        }
+    }
+}
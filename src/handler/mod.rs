@@ -1,3 +1,4 @@
+mod compression;
 pub mod endpoints;
 mod parsers;
 pub mod responses;
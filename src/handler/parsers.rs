@@ -1,3 +1,16 @@
+use crate::cache::ftp::Conditions;
+use crate::cache::FtpClientID;
+use crate::handler::responses::ErrorMessage;
+use cookie::Cookie;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderName, COOKIE, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use hyper::http::request::Parts;
+use hyper::{Body, StatusCode};
+use serde::de::DeserializeOwned;
+
+/// Maximum size of the JSON request body
+const JSON_BODY_MAX_SIZE: u64 = 1024 * 64;
+
 /// Tries to match most popular extensions with proper
 /// [mime type](https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types)
 ///
@@ -41,3 +54,98 @@ pub fn extension_to_mime(ext: impl AsRef<str>) -> &'static str {
         &_ => "application/octet-stream",
     }
 }
+
+/// Returns the decoded value of the uri query parameter
+pub fn query_param(meta: &Parts, name: &str) -> Option<String> {
+    meta.uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if key == name {
+            urlencoding::decode(value)
+                .ok()
+                .map(|value| value.to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Reads the conditional headers of the request
+pub fn conditions(meta: &Parts) -> Conditions {
+    let header = |name: HeaderName| {
+        meta.headers
+            .get(name)
+            .map(|value| value.to_str().unwrap_or_default().to_string())
+    };
+
+    Conditions {
+        if_none_match: header(IF_NONE_MATCH),
+        if_modified_since: header(IF_MODIFIED_SINCE),
+    }
+}
+
+/// Reads the ftp client id from the "ftp" header or the cookie
+///
+/// Returns `None` if the id is missing or empty
+pub fn ftp_client_id(meta: &Parts) -> Option<FtpClientID> {
+    let mut id = String::new();
+    if let Some(ftp) = meta.headers.get("ftp") {
+        id = ftp.to_str().unwrap_or("").to_string();
+    } else if let Some(cookie) = meta.headers.get(COOKIE) {
+        if let Ok(cookie) = Cookie::parse(cookie.to_str().unwrap_or("")) {
+            id = cookie.value().to_string();
+        }
+    }
+
+    if id.is_empty() {
+        None
+    } else {
+        Some(FtpClientID::new(id))
+    }
+}
+
+/// Returns the decoded part of the uri path after the endpoint's path
+///
+/// Example: <br>
+/// "/api/1/ftp/zet/my%20file.txt" with the "ftp" endpoint returns "/zet/my file.txt"
+pub fn expanded_path(meta: &Parts, endpoint: &str) -> Option<String> {
+    let mut path = urlencoding::decode(meta.uri.path())
+        .unwrap_or_default()
+        .to_string();
+    let prefix = format!("/{endpoint}");
+
+    let i = path.find(&prefix)?;
+    path.replace_range(..i + prefix.len(), "");
+    Some(path)
+}
+
+/// Reads the entire body and deserializes it from the JSON format,
+/// bodies bigger than `JSON_BODY_MAX_SIZE` are rejected with 413
+pub async fn body_json<T: DeserializeOwned>(mut body: Body) -> Result<T, ErrorMessage> {
+    let too_large =
+        || ErrorMessage::new("request body is too large", StatusCode::PAYLOAD_TOO_LARGE);
+
+    // the Content-Length is checked before anything is read
+    if body.size_hint().lower() > JSON_BODY_MAX_SIZE {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|error| {
+            ErrorMessage::new(
+                "cannot read bytes from the body",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .error(error)
+        })?;
+
+        if (bytes.len() + chunk.len()) as u64 > JSON_BODY_MAX_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    serde_json::from_slice::<T>(&bytes).map_err(|error| {
+        ErrorMessage::new("invalid json data", StatusCode::BAD_REQUEST).error_force(error)
+    })
+}
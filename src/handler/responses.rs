@@ -1,10 +1,21 @@
 use super::*;
-use crate::cache::ftp::FileContentPack;
+use crate::cache::ftp::{etag_digest, Conditions, FileContentPack, FileStream, StreamRange};
+use crate::handler::compression::{self, Encoding};
 use crate::handler::parsers::extension_to_mime;
+use crate::utils::throttle::{Direction, Limiter};
+use crate::utils::time;
+use hyper::body::Bytes;
+use hyper::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_ENCODING,
+    CONTENT_LENGTH, CONTENT_RANGE, CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+    VARY, X_CONTENT_TYPE_OPTIONS,
+};
 use hyper::http::response::Builder;
 use hyper::StatusCode;
 use std::error::Error;
 use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ErrorMessage {
@@ -57,6 +68,25 @@ pub fn e404() -> Response<Body> {
         .unwrap()
 }
 
+/// Builds the Content-Disposition header value,
+/// non ASCII file names are encoded according to the RFC 5987
+///
+/// * `disposition` - "attachment" or "inline"
+pub fn content_disposition(disposition: &str, file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+
+    format!(
+        "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        urlencoding::encode(file_name)
+    )
+}
+
 /// Parses FileContentPack into a response
 pub fn file_content_pack(content_pack: FileContentPack, ftp_path: &Path) -> Response<Body> {
     let file_name = ftp_path
@@ -71,7 +101,7 @@ pub fn file_content_pack(content_pack: FileContentPack, ftp_path: &Path) -> Resp
             .unwrap_or_default(),
     );
 
-    Builder::new()
+    let mut builder = Builder::new()
         .status(StatusCode::PARTIAL_CONTENT)
         // .header(CONTENT_TYPE, mime)
         // .header(CONTENT_DISPOSITION, format!("attachment; filename=\"{file_name}\""))
@@ -81,6 +111,239 @@ pub fn file_content_pack(content_pack: FileContentPack, ftp_path: &Path) -> Resp
         .header("pack-size", content_pack.size)
         .header("pack-is-last", content_pack.is_last.to_string())
         .header("pack-mime", mime.to_string())
-        .body(Body::from(content_pack.bytes))
+        .header("pack-chunk-size", content_pack.chunk_size)
+        .header("pack-transfer", content_pack.transfer)
+        .header("pack-sha256", content_pack.checksum)
+        .header(ACCEPT_RANGES, "bytes");
+
+    if let Some(file_checksum) = content_pack.file_checksum {
+        builder = builder.header("pack-file-sha256", file_checksum);
+    }
+
+    builder.body(Body::from(content_pack.bytes)).unwrap()
+}
+
+/// 304 for the file which has not changed since the client's copy
+pub fn not_modified(etag: String, modified: i64) -> Response<Body> {
+    Builder::new()
+        .status(StatusCode::NOT_MODIFIED)
+        .header(ETAG, etag)
+        .header(LAST_MODIFIED, time::http_date(modified))
+        .header(CACHE_CONTROL, "private, no-cache")
+        .body(Body::empty())
         .unwrap()
 }
+
+/// Creates the directory listing response, 304 if the client's copy is up to date.
+///
+/// The ETag is the digest of the listing, listings are small enough to be compressed at once.
+pub fn listing(
+    data: String,
+    encoding: Option<Encoding>,
+    conditions: &Conditions,
+) -> Response<Body> {
+    let etag = format!("\"{}\"", etag_digest(data.as_bytes()));
+    let compressed = encoding.and_then(|encoding| {
+        compression::compress(data.as_bytes(), encoding)
+            .ok()
+            .map(|compressed| (encoding, compressed))
+    });
+
+    let builder = Builder::new()
+        .header(VARY, ACCEPT_ENCODING)
+        .header(CACHE_CONTROL, "private, no-cache");
+
+    if conditions.not_modified(&etag, None) {
+        let etag = match &compressed {
+            Some((encoding, _)) => encoding.etag(&etag),
+            None => etag,
+        };
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .header(ETAG, etag)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    match compressed {
+        Some((encoding, compressed)) => builder
+            .status(StatusCode::OK)
+            .header(ETAG, encoding.etag(&etag))
+            .header(CONTENT_ENCODING, encoding.name())
+            .body(Body::from(compressed))
+            .unwrap(),
+        None => builder
+            .status(StatusCode::OK)
+            .header(ETAG, etag)
+            .body(Body::from(data))
+            .unwrap(),
+    }
+}
+
+/// Parses FileStream into a response,
+/// 206 with the Content-Range if the range was requested, 304 if the client's copy is up to date
+/// and 200 otherwise
+///
+/// The full content of the compressible file is compressed with the negotiated encoding,
+/// the partial content is always sent as it is.
+///
+/// Inline files are displayed by the browser, so they are isolated with the restrictive
+/// Content-Security-Policy and their mime type cannot be sniffed.
+pub fn file_stream(
+    file_stream: FileStream,
+    ftp_path: &Path,
+    encoding: Option<Encoding>,
+    inline: bool,
+) -> Response<Body> {
+    let file_name = ftp_path
+        .file_name()
+        .map(|str| str.to_string_lossy().to_string())
+        .unwrap_or(String::from("unknwon_file.bin"));
+
+    let mime = extension_to_mime(
+        ftp_path
+            .extension()
+            .map(|str| str.to_str().unwrap_or_default())
+            .unwrap_or_default(),
+    );
+
+    let disposition = if inline { "inline" } else { "attachment" };
+    let compressible = compression::is_compressible(mime);
+    let encoding = encoding
+        .filter(|_| compressible)
+        .filter(|_| matches!(file_stream.range, StreamRange::Full));
+
+    let mut builder = Builder::new()
+        .header(LAST_MODIFIED, file_stream.last_modified())
+        .header(CACHE_CONTROL, "private, no-cache");
+    if inline {
+        builder = builder
+            .header(CONTENT_SECURITY_POLICY, inline_csp(mime))
+            .header(X_CONTENT_TYPE_OPTIONS, "nosniff");
+    }
+    if compressible {
+        builder = builder.header(VARY, ACCEPT_ENCODING);
+    }
+
+    // the encoded content is a different representation of the file
+    let builder = match encoding {
+        Some(encoding) => builder.header(ETAG, encoding.etag(&file_stream.etag())),
+        None => builder.header(ETAG, file_stream.etag()),
+    };
+
+    if file_stream.not_modified {
+        return builder
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    if let Some(encoding) = encoding {
+        return builder
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime)
+            .header(CONTENT_ENCODING, encoding.name())
+            .header(
+                CONTENT_DISPOSITION,
+                content_disposition(disposition, &file_name),
+            )
+            .body(channel_body(compression::compress_chunks(
+                file_stream.chunks,
+                encoding,
+            )))
+            .unwrap();
+    }
+    let builder = builder.header(ACCEPT_RANGES, "bytes");
+
+    let builder = match file_stream.range {
+        StreamRange::Full => builder.status(StatusCode::OK),
+        StreamRange::Partial(range) => builder.status(StatusCode::PARTIAL_CONTENT).header(
+            CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end, file_stream.size),
+        ),
+        StreamRange::Unsatisfiable => {
+            return builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{}", file_stream.size))
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+
+    builder
+        .header(CONTENT_TYPE, mime)
+        .header(CONTENT_LENGTH, file_stream.content_length())
+        .header(
+            CONTENT_DISPOSITION,
+            content_disposition(disposition, &file_name),
+        )
+        .body(channel_body(file_stream.chunks))
+        .unwrap()
+}
+
+/// Content-Security-Policy of the inline files, the content cannot run any scripts
+/// or load resources from the other origins
+fn inline_csp(mime: &str) -> String {
+    let policy = "default-src 'none'; img-src 'self' data:; media-src 'self'; \
+        style-src 'unsafe-inline'; object-src 'self'";
+
+    // browsers refuse to display pdf files in the sandbox
+    if mime == "application/pdf" {
+        policy.to_string()
+    } else {
+        format!("{policy}; sandbox")
+    }
+}
+
+/// Forwards the chunks from the channel into the response body,
+/// the body is aborted if any chunk fails
+pub fn channel_body(mut chunks: mpsc::Receiver<anyhow::Result<Vec<u8>>>) -> Body {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        while let Some(chunk) = chunks.recv().await {
+            match chunk {
+                Ok(bytes) => {
+                    if sender.send_data(Bytes::from(bytes)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    body
+}
+
+/// Streams the local file into the response body,
+/// the speed is limited only by the global download limit
+pub fn local_file_body(mut file: tokio::fs::File) -> Body {
+    let (mut sender, body) = Body::channel();
+    let limiter = Limiter::new(Direction::Download, None);
+
+    tokio::spawn(async move {
+        let mut buffer = vec![0u8; 1024 * 64];
+        loop {
+            match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(read_size) => {
+                    limiter.consume(read_size as u64).await;
+                    let bytes = Bytes::copy_from_slice(&buffer[..read_size]);
+                    if sender.send_data(bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    body
+}
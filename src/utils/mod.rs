@@ -3,6 +3,7 @@
 
 mod optional_bool;
 mod pjatk_tools;
+pub mod throttle;
 pub mod time;
 
 pub use optional_bool::*;
@@ -0,0 +1,186 @@
+use crate::cache::FtpClientID;
+use crate::CONFIG;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets of the sessions which haven't transferred anything for this long are removed
+const SESSION_BUCKET_IDLE: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref BUCKETS: Mutex<Buckets> = Mutex::new(Buckets::default());
+}
+
+/// Direction of the transferred bytes, each one is limited separately
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+impl Direction {
+    /// Global and per session limits in bytes per second, 0 means no limit
+    fn limits(&self) -> (u64, u64) {
+        match self {
+            Self::Download => (
+                CONFIG.download_rate_limit,
+                CONFIG.session_download_rate_limit,
+            ),
+            Self::Upload => (CONFIG.upload_rate_limit, CONFIG.session_upload_rate_limit),
+        }
+    }
+}
+
+/// Token bucket which allows a burst of one second of the transfer
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last_usage: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_usage: Instant::now(),
+        }
+    }
+
+    /// Takes the tokens and returns how long the caller has to wait to stay within the rate.
+    ///
+    /// The tokens can go below zero, so the concurrent callers wait longer.
+    fn take(&mut self, amount: u64) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_usage).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate) - amount as f64;
+        self.last_usage = now;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    global: HashMap<Direction, TokenBucket>,
+    sessions: HashMap<(FtpClientID, Direction), TokenBucket>,
+}
+
+/// Limits the rate of the transferred bytes for the session and for the whole server
+pub struct Limiter {
+    direction: Direction,
+    session: Option<FtpClientID>,
+}
+
+impl Limiter {
+    /// * `session` - sftp client of the transfer, public transfers are limited only globally
+    pub fn new(direction: Direction, session: Option<FtpClientID>) -> Self {
+        Self { direction, session }
+    }
+
+    /// Registers the transferred bytes and returns how long the transfer has to wait
+    pub fn delay(&self, bytes: u64) -> Duration {
+        let (global_limit, session_limit) = self.direction.limits();
+        if global_limit == 0 && session_limit == 0 {
+            return Duration::ZERO;
+        }
+
+        let mut buckets = BUCKETS.lock().unwrap();
+        let mut delay = Duration::ZERO;
+
+        if global_limit > 0 {
+            delay = buckets
+                .global
+                .entry(self.direction)
+                .or_insert_with(|| TokenBucket::new(global_limit))
+                .take(bytes);
+        }
+
+        if let (Some(session), true) = (&self.session, session_limit > 0) {
+            let key = (session.clone(), self.direction);
+            if !buckets.sessions.contains_key(&key) {
+                buckets
+                    .sessions
+                    .retain(|_, bucket| bucket.last_usage.elapsed() < SESSION_BUCKET_IDLE);
+            }
+
+            let session_delay = buckets
+                .sessions
+                .entry(key)
+                .or_insert_with(|| TokenBucket::new(session_limit))
+                .take(bytes);
+            delay = delay.max(session_delay);
+        }
+
+        delay
+    }
+
+    /// Waits until the bytes can be transferred, used in the blocking sftp operations
+    pub fn consume_blocking(&self, bytes: u64) {
+        let delay = self.delay(bytes);
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Waits until the bytes can be transferred
+    pub async fn consume(&self, bytes: u64) {
+        let delay = self.delay(bytes);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::prelude::v1::test;
+
+    /// Tolerance for the time passed between the calls
+    const EPSILON: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn burst_of_one_second_is_free() {
+        let mut bucket = TokenBucket::new(1000);
+        assert_eq!(bucket.take(600), Duration::ZERO);
+        assert_eq!(bucket.take(400), Duration::ZERO);
+    }
+
+    #[test]
+    fn exceeding_the_rate_waits() {
+        let mut bucket = TokenBucket::new(1000);
+        let delay = bucket.take(1500);
+        assert!(delay > Duration::from_millis(450) && delay <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn concurrent_callers_wait_longer() {
+        let mut bucket = TokenBucket::new(1000);
+        let first = bucket.take(2000);
+        let second = bucket.take(1000);
+        assert!(first <= Duration::from_secs(1));
+        assert!(second > first + Duration::from_secs(1) - EPSILON);
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let mut bucket = TokenBucket::new(1000);
+        bucket.take(1000);
+        bucket.last_usage -= Duration::from_millis(500);
+        assert_eq!(bucket.take(500), Duration::ZERO);
+        assert!(bucket.take(500) > Duration::from_millis(450));
+    }
+
+    #[test]
+    fn refill_is_capped_at_the_rate() {
+        let mut bucket = TokenBucket::new(1000);
+        bucket.last_usage -= Duration::from_secs(10);
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        assert!(bucket.take(1000) > Duration::from_secs(1) - EPSILON);
+    }
+}
@@ -4,3 +4,18 @@
 pub fn now() -> i64 {
     chrono::Local::now().timestamp()
 }
+
+/// Formats the unix timestamp as the HTTP date (RFC 7231)
+pub fn http_date(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Parses the HTTP date into the unix timestamp
+pub fn parse_http_date(date: &str) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc2822(date.trim())
+        .ok()
+        .map(|date| date.timestamp())
+}